@@ -0,0 +1,25 @@
+#!/bin/sh
+# Runs the main.rs workloads of the working tree against the ones of another
+# revision, HEAD unless given, alternating between the two release builds.
+#
+#   $ ./extra/compare.sh [rev] [runs]
+set -e
+
+rev=${1:-HEAD}
+runs=${2:-5}
+
+base=$(mktemp -d)
+trap 'git worktree remove --force "$base"' EXIT
+git worktree add -q --detach "$base" "$rev"
+
+(cd "$base" && cargo build -q --release)
+cargo build -q --release
+
+for _ in $(seq "$runs"); do
+    for build in "$base" .; do
+        label=$([ "$build" = . ] && echo tree || echo "$rev")
+        printf '%-8s' "$label"
+        "$build/target/release/ring" 2>&1 >/dev/null | grep -E '^(chan|ring|stamped) ' | tr '\n' ' '
+        echo
+    done
+done
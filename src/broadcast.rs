@@ -0,0 +1,613 @@
+//! Single producer ring where every subscriber gets a clone of every item
+//! published after it subscribed. A `lossy_broadcast` ring overwrites the
+//! oldest item instead of waiting for the slowest subscriber.
+
+use core::fmt;
+use core::hint::spin_loop;
+use core::mem;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+
+use crate::buffer::{Buffer, Storage};
+use crate::cursor::Cursor;
+
+const WRITER: u32 = 1 << 31;
+const INIT: u32 = 1 << 30;
+const READERS: u32 = INIT - 1;
+
+const FREE: u8 = 0;
+const CLAIMED: u8 = 1;
+const ACTIVE: u8 = 2;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum TryRecvError {
+    Empty,
+    Lagged(u64),
+    Disconnected,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum RecvError {
+    Lagged(u64),
+    Disconnected,
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            TryRecvError::Empty => "receiving on an empty broadcast".fmt(f),
+            TryRecvError::Lagged(n) => write!(f, "subscriber lagged behind by {} messages", n),
+            TryRecvError::Disconnected => "receiving on a closed broadcast".fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            RecvError::Lagged(n) => write!(f, "subscriber lagged behind by {} messages", n),
+            RecvError::Disconnected => "receiving on a closed broadcast".fmt(f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TryRecvError {}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RecvError {}
+
+// per slot the position of the item it holds and a small reader/writer lock,
+// subscribers clone under the read lock so the publisher can never drop an
+// item from under them when it laps a slow subscriber
+struct Slot {
+    stamp: AtomicU32,
+    state: AtomicU32,
+}
+
+impl Slot {
+    #[inline]
+    fn lock_read(&self) -> ReadLock<'_> {
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & WRITER == 0 && self.state
+                .compare_exchange_weak(state, state + 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok() {
+                return ReadLock(self);
+            }
+            spin_loop();
+        }
+    }
+
+    // returns whether the slot held an item
+    #[inline]
+    fn lock_write(&self) -> bool {
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & READERS == 0 && self.state
+                .compare_exchange_weak(state, state | WRITER, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok() {
+                return state & INIT != 0;
+            }
+            spin_loop();
+        }
+    }
+
+    #[inline]
+    fn unlock_write(&self) {
+        self.state.store(INIT, Ordering::Release);
+    }
+}
+
+// a held read lock, released on drop so that a panicking `T::clone` does
+// not leave the slot locked under the publisher
+struct ReadLock<'a>(&'a Slot);
+
+impl Drop for ReadLock<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        self.0.state.fetch_sub(1, Ordering::Release);
+    }
+}
+
+// a subscriber cursor, its tail is the next position to read
+struct Tap {
+    state: AtomicU8,
+    cursor: Cursor,
+}
+
+struct Shared<T> {
+    prod: Cursor,
+    taps: Box<[Tap]>,
+    slots: Box<[Slot]>,
+    inner: Buffer<T>,
+    lossy: bool,
+    closed: AtomicBool,
+}
+
+unsafe impl<T: Send + Sync> Send for Shared<T> {}
+unsafe impl<T: Send + Sync> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    fn new(log2: usize, taps: usize, lossy: bool) -> Self {
+        assert!(log2 > 1, "log2 must give greather than 1");
+        assert!(log2 < 32, "log2 must give less than 32");
+        assert!(taps > 0, "taps must be greather than zero");
+        assert!(mem::size_of::<T>() > 0, "value size must be greather than zero");
+
+        let size : usize = 1 << log2;
+
+        Shared {
+            prod: Cursor::new(size as u32),
+            taps: (0..taps)
+                .map(|_| Tap { state: AtomicU8::new(FREE), cursor: Cursor::new(size as u32) })
+                .collect(),
+            slots: (0..size)
+                .map(|_| Slot { stamp: AtomicU32::new(0), state: AtomicU32::new(0) })
+                .collect(),
+            inner: Buffer::new(size),
+            lossy,
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    // how far the slowest active subscriber is behind `head`, the `SeqCst`
+    // load pairs with the producer tail store as explained in `join`
+    #[inline]
+    fn lag(&self, head: u32) -> u32 {
+        self.taps.iter()
+            .filter(|tap| tap.state.load(Ordering::SeqCst) == ACTIVE)
+            .map(|tap| head.wrapping_sub(tap.cursor.back()))
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn join(&self) -> Option<usize> {
+        let id = self.taps.iter().position(|tap| tap.state
+            .compare_exchange(FREE, CLAIMED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok())?;
+        let tap = &self.taps[id];
+
+        // the publisher ignores the tap until it is active and may still be
+        // writing the slot it loaded before it saw it, so the start position
+        // is loaded again once active: that slot is then at or past it. The
+        // state store and the reload are `SeqCst` as are the producer tail
+        // store and the state load in `lag`, so that one of the two sides
+        // always sees the other's store
+        tap.cursor.tail.store(self.prod.back(), Ordering::Relaxed);
+        tap.state.store(ACTIVE, Ordering::SeqCst);
+        tap.cursor.tail.store(self.prod.tail.load(Ordering::SeqCst), Ordering::Release);
+
+        Some(id)
+    }
+
+    #[inline]
+    fn leave(&self, id: usize) {
+        self.taps[id].state.store(FREE, Ordering::Release);
+    }
+
+    // single producer only, guaranteed by `Publisher` taking `&mut self`
+    #[inline]
+    fn publish(&self, value: T) -> Option<T> {
+        let head = self.prod.front();
+        let next = self.prod.next(head);
+
+        if !self.lossy && self.lag(head) >= self.prod.size() {
+            return Some(value);
+        }
+
+        let index = self.prod.index(head);
+        let slot = &self.slots[index];
+
+        if slot.lock_write() {
+            unsafe { self.inner.drop_at(index) };
+        }
+        self.inner.write(index, value);
+        slot.stamp.store(head, Ordering::Relaxed);
+        slot.unlock_write();
+
+        self.prod.head.store(next, Ordering::Release);
+        self.prod.tail.store(next, Ordering::SeqCst);
+
+        None
+    }
+
+    #[inline]
+    fn receive(&self, id: usize) -> Result<T, TryRecvError>
+    where
+        T: Clone,
+    {
+        let cursor = &self.taps[id].cursor;
+        let size = self.prod.size();
+        let pos = cursor.back();
+        let tail = self.prod.back();
+
+        if pos == tail {
+            // the publisher marks the ring closed after its last publish
+            if self.closed.load(Ordering::Acquire) && self.prod.reached(pos) {
+                return Err(TryRecvError::Disconnected);
+            }
+            return Err(TryRecvError::Empty);
+        }
+
+        if tail.wrapping_sub(pos) > size {
+            return Err(self.skip(cursor, pos));
+        }
+
+        let index = self.prod.index(pos);
+        let slot = &self.slots[index];
+
+        let lock = slot.lock_read();
+        if slot.stamp.load(Ordering::Relaxed) != pos {
+            drop(lock);
+            return Err(self.skip(cursor, pos));
+        }
+        let value = unsafe { self.inner.at(index) }.clone();
+        drop(lock);
+
+        cursor.tail.store(cursor.next(pos), Ordering::Release);
+
+        Ok(value)
+    }
+
+    // moves a lapped subscriber to the oldest item still in the ring, the
+    // slot at `pos` is known to be overwritten so at least one is skipped
+    #[cold]
+    fn skip(&self, cursor: &Cursor, pos: u32) -> TryRecvError {
+        let size = self.prod.size();
+        let lag = self.prod.back().wrapping_sub(pos);
+        let skipped = if lag > size { lag - size } else { 1 };
+
+        cursor.tail.store(pos.wrapping_add(skipped), Ordering::Release);
+
+        TryRecvError::Lagged(skipped as u64)
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        for (index, slot) in self.slots.iter().enumerate() {
+            if slot.state.load(Ordering::Relaxed) & INIT != 0 {
+                unsafe { self.inner.drop_at(index) };
+            }
+        }
+    }
+}
+
+/// The single writing end of a broadcast ring.
+pub struct Publisher<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// One reading end of a broadcast ring, sees every item published after
+/// it subscribed. Dropping it frees its tap for a new subscriber.
+pub struct Subscriber<T> {
+    shared: Arc<Shared<T>>,
+    id: usize,
+}
+
+impl<T> Publisher<T> {
+    /// Publishes the item, or gives it back if the slowest subscriber is a
+    /// full lap behind. A lossy ring never gives it back.
+    #[inline]
+    pub fn try_send(&mut self, value: T) -> Option<T> {
+        self.shared.publish(value)
+    }
+
+    /// Publishes the item, spinning while the slowest subscriber is a full
+    /// lap behind.
+    #[inline]
+    pub fn send(&mut self, mut value: T) {
+        while let Some(v) = self.shared.publish(value) {
+            value = v;
+            spin_loop();
+        }
+    }
+
+    /// A new subscriber starting at the next published item, `None` if
+    /// all taps are taken.
+    #[inline]
+    pub fn subscribe(&self) -> Option<Subscriber<T>> {
+        subscribe(&self.shared)
+    }
+
+    /// Number of subscribers currently attached.
+    #[inline]
+    pub fn subscribers(&self) -> usize {
+        self.shared.taps.iter()
+            .filter(|tap| tap.state.load(Ordering::Relaxed) == ACTIVE)
+            .count()
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.shared.prod.size() as usize
+    }
+
+    #[inline]
+    pub fn is_lossy(&self) -> bool {
+        self.shared.lossy
+    }
+}
+
+impl<T> Drop for Publisher<T> {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+    }
+}
+
+impl<T: Clone> Subscriber<T> {
+    #[inline]
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.shared.receive(self.id)
+    }
+
+    /// Spins until an item is published, the subscriber is found lagging
+    /// or the publisher is gone and everything has been read.
+    #[inline]
+    pub fn recv(&mut self) -> Result<T, RecvError> {
+        loop {
+            match self.shared.receive(self.id) {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Lagged(n)) => return Err(RecvError::Lagged(n)),
+                Err(TryRecvError::Disconnected) => return Err(RecvError::Disconnected),
+                Err(TryRecvError::Empty) => spin_loop(),
+            }
+        }
+    }
+}
+
+impl<T> Subscriber<T> {
+    /// A new subscriber starting at the next published item, `None` if
+    /// all taps are taken.
+    #[inline]
+    pub fn resubscribe(&self) -> Option<Subscriber<T>> {
+        subscribe(&self.shared)
+    }
+
+    /// Number of published items this subscriber has not read yet, at most
+    /// the capacity even if it was lapped.
+    #[inline]
+    pub fn len(&self) -> usize {
+        let pos = self.shared.taps[self.id].cursor.back();
+        let tail = self.shared.prod.back();
+        tail.wrapping_sub(pos).min(self.shared.prod.size()) as usize
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Drop for Subscriber<T> {
+    fn drop(&mut self) {
+        self.shared.leave(self.id);
+    }
+}
+
+#[inline]
+fn subscribe<T>(shared: &Arc<Shared<T>>) -> Option<Subscriber<T>> {
+    let id = shared.join()?;
+    Some(Subscriber { shared: Arc::clone(shared), id })
+}
+
+/// A broadcast ring of `2^log2` slots for up to `taps` subscribers at a
+/// time, the publisher waits for the slowest of them.
+pub fn broadcast<T: Clone>(log2: usize, taps: usize) -> Publisher<T> {
+    Publisher { shared: Arc::new(Shared::new(log2, taps, false)) }
+}
+
+/// A broadcast ring of `2^log2` slots for up to `taps` subscribers at a
+/// time, the publisher overwrites the oldest item and lapped subscribers
+/// get `Lagged(n)`.
+pub fn lossy_broadcast<T: Clone>(log2: usize, taps: usize) -> Publisher<T> {
+    Publisher { shared: Arc::new(Shared::new(log2, taps, true)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+    use std::thread;
+    use std::vec::Vec;
+    use super::*;
+    use crate::test_util::Drops;
+
+    #[test]
+    fn test_fan_out() {
+        const N : usize = 100_000;
+        const SUBS : usize = 3;
+
+        let mut publisher = broadcast::<usize>(4, SUBS);
+        let handles = (0..SUBS)
+            .map(|_| {
+                let mut sub = publisher.subscribe().unwrap();
+                thread::spawn(move || {
+                    let mut sum = 0;
+                    for i in 0..N {
+                        let value = loop {
+                            match sub.try_recv() {
+                                Ok(value) => break value,
+                                Err(TryRecvError::Empty) => thread::yield_now(),
+                                Err(e) => panic!("{:?}", e),
+                            }
+                        };
+                        assert_eq!(value, i);
+                        sum += value;
+                    }
+                    assert_eq!(sub.recv(), Err(RecvError::Disconnected));
+                    sum
+                })
+            })
+            .collect::<Vec<_>>();
+
+        assert!(publisher.subscribe().is_none());
+
+        for i in 0..N {
+            let mut value = i;
+            while let Some(v) = publisher.try_send(value) {
+                value = v;
+                thread::yield_now();
+            }
+        }
+        drop(publisher);
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), (0..N).sum());
+        }
+    }
+
+    #[test]
+    fn test_slowest_subscriber() {
+        let mut publisher = broadcast::<usize>(2, 2);
+        let mut fast = publisher.subscribe().unwrap();
+        let mut slow = publisher.subscribe().unwrap();
+
+        for i in 0..4 {
+            assert_eq!(publisher.try_send(i), None);
+        }
+        for i in 0..4 {
+            assert_eq!(fast.try_recv(), Ok(i));
+        }
+        assert_eq!(fast.try_recv(), Err(TryRecvError::Empty));
+
+        // the slow subscriber still holds every slot
+        assert_eq!(publisher.try_send(4), Some(4));
+        assert_eq!(slow.try_recv(), Ok(0));
+        assert_eq!(publisher.try_send(4), None);
+        assert_eq!(publisher.try_send(5), Some(5));
+
+        // leaving releases them
+        drop(slow);
+        assert_eq!(publisher.subscribers(), 1);
+        assert_eq!(publisher.try_send(5), None);
+        assert_eq!(fast.len(), 2);
+    }
+
+    #[test]
+    fn test_join_leave() {
+        let mut publisher = broadcast::<usize>(2, 1);
+        publisher.send(0);
+
+        let mut sub = publisher.subscribe().unwrap();
+        assert!(publisher.subscribe().is_none());
+        assert!(sub.resubscribe().is_none());
+
+        // a new subscriber only sees what comes after it
+        assert_eq!(sub.try_recv(), Err(TryRecvError::Empty));
+        publisher.send(1);
+        assert_eq!(sub.try_recv(), Ok(1));
+
+        drop(sub);
+        assert_eq!(publisher.subscribers(), 0);
+        let mut sub = publisher.subscribe().unwrap();
+        publisher.send(2);
+        assert_eq!(sub.try_recv(), Ok(2));
+    }
+
+    #[test]
+    fn test_lossy_lagged() {
+        let mut publisher = lossy_broadcast::<usize>(2, 1);
+        let mut sub = publisher.subscribe().unwrap();
+        assert!(publisher.is_lossy());
+
+        for i in 0..10 {
+            assert_eq!(publisher.try_send(i), None);
+        }
+
+        assert_eq!(sub.len(), 4);
+        assert_eq!(sub.try_recv(), Err(TryRecvError::Lagged(6)));
+        for i in 6..10 {
+            assert_eq!(sub.try_recv(), Ok(i));
+        }
+        assert_eq!(sub.try_recv(), Err(TryRecvError::Empty));
+
+        drop(publisher);
+        assert_eq!(sub.recv(), Err(RecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_lossy_concurrent() {
+        const N : usize = 100_000;
+
+        let mut publisher = lossy_broadcast::<usize>(3, 2);
+        let handles = (0..2)
+            .map(|_| {
+                let mut sub = publisher.subscribe().unwrap();
+                thread::spawn(move || {
+                    let (mut seen, mut lagged, mut last) = (0, 0, None);
+                    loop {
+                        match sub.try_recv() {
+                            Ok(value) => {
+                                // items arrive in order even across a lag
+                                assert!(last.is_none_or(|last| value > last));
+                                last = Some(value);
+                                seen += 1;
+                            },
+                            Err(TryRecvError::Lagged(n)) => lagged += n as usize,
+                            Err(TryRecvError::Empty) => thread::yield_now(),
+                            Err(TryRecvError::Disconnected) => break,
+                        }
+                    }
+                    seen + lagged
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for i in 0..N {
+            publisher.send(i);
+        }
+        drop(publisher);
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), N);
+        }
+    }
+
+    // panics when cloned if broken
+    struct Fragile(bool);
+
+    impl Clone for Fragile {
+        fn clone(&self) -> Self {
+            assert!(!self.0, "clone");
+            Fragile(self.0)
+        }
+    }
+
+    #[test]
+    fn test_clone_panic() {
+        let mut publisher = lossy_broadcast::<Fragile>(2, 1);
+        let mut sub = publisher.subscribe().unwrap();
+        publisher.send(Fragile(true));
+
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| sub.try_recv()));
+        assert!(result.is_err());
+
+        // the read lock went with the unwind, the slot can be written again
+        assert_eq!(publisher.shared.slots[0].state.load(Ordering::Relaxed) & READERS, 0);
+        for _ in 0..4 {
+            publisher.send(Fragile(true));
+        }
+    }
+
+    #[test]
+    fn test_drop() {
+        let drops = Drops::new();
+        {
+            let mut publisher = lossy_broadcast(2, 1);
+            let mut sub = publisher.subscribe().unwrap();
+            for _ in 0..6 {
+                publisher.send(drops.item());
+            }
+            // two evicted by the publisher
+            assert_eq!(drops.count(), 2);
+
+            // reading clones, the ring keeps its items
+            drop(sub.try_recv());
+            drop(sub.try_recv().unwrap());
+            assert_eq!(drops.count(), 3);
+        }
+        assert_eq!(drops.count(), 7);
+    }
+}
@@ -1,90 +1,202 @@
-use std::mem;
-use std::ptr;
-use std::marker::PhantomData;
-use std::fmt::{self, Debug};
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ptr;
+use core::fmt::{self, Debug};
+use alloc::boxed::Box;
 
+/// Slot storage behind a ring.
+///
+/// Slots start out uninitialised and the storage never knows which of them
+/// hold a value: that is tracked by the ring cursors, so reading and
+/// dropping are left to the caller and the storage itself never drops a `T`.
+///
+/// # Safety
+///
+/// `slot` must return a valid, properly aligned pointer for every index
+/// below `size`, distinct for distinct indices, and stable for as long as
+/// the storage is alive; `size` must be a power of two. A ring calls
+/// `slot` from several threads at once, for distinct indices.
+pub unsafe trait Storage<T> {
+    fn size(&self) -> usize;
+
+    fn slot(&self, index: usize) -> *mut MaybeUninit<T>;
+
+    #[inline]
+    fn as_ptr(&self, index: usize) -> *const T {
+        self.slot(index) as *const T
+    }
+
+    #[inline]
+    fn as_mut_ptr(&self, index: usize) -> *mut T {
+        self.slot(index) as *mut T
+    }
+
+    /// Overwrites the slot without dropping what it may hold.
+    #[inline]
+    fn write(&self, index: usize, value: T) {        
+        unsafe { ptr::write(self.as_mut_ptr(index), value) }
+    }
+
+    /// Moves the value out, leaving the slot uninitialised.
+    ///
+    /// # Safety
+    ///
+    /// The slot must be initialised.
+    #[inline]
+    unsafe fn read(&self, index: usize) -> T {
+        ptr::read(self.as_ptr(index))
+    }
+
+    /// # Safety
+    ///
+    /// The slot must be initialised.
+    #[inline]
+    unsafe fn at(&self, index: usize) -> &T {
+        &*self.as_ptr(index)
+    }
+
+    /// # Safety
+    ///
+    /// The slot must be initialised.
+    #[inline]
+    unsafe fn at_mut(&mut self, index: usize) -> &mut T {
+        &mut *self.as_mut_ptr(index)
+    }
+
+    /// Drops the value in place, leaving the slot uninitialised.
+    ///
+    /// # Safety
+    ///
+    /// The slot must be initialised.
+    #[inline]
+    unsafe fn drop_at(&self, index: usize) {
+        ptr::drop_in_place(self.as_mut_ptr(index))
+    }
+}
+
+/// Heap allocated storage, sized at runtime.
 #[repr(align(64))]
 pub struct Buffer<T> {
-    inner: *mut T,
-    pub size: usize,
-    _marker: PhantomData<T>,
+    inner: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    size: usize,
+    // one loom cell per slot, touched on every access for loom to catch
+    // accesses the cursors do not order
+    #[cfg(loom)]
+    shadow: Box<[loom::cell::UnsafeCell<()>]>,
 }
 
-#[allow(dead_code)]
 impl<T> Buffer<T> {
     
     #[inline]
     pub fn new(size : usize) -> Self {
-        let inner = {
-            let mut v = Vec::<T>::with_capacity(size);
-            let buffer = v.as_mut_ptr();
-            mem::forget(v);
-            buffer
-        };
+        assert!(size.is_power_of_two(), "size must be a power of two");
+
+        let inner = (0..size)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect();
         
         Buffer { 
             inner,
             size,
-            _marker: PhantomData,
+            #[cfg(loom)]
+            shadow: (0..size).map(|_| loom::cell::UnsafeCell::new(())).collect(),
         }
     }
+}
 
+unsafe impl<T> Storage<T> for Buffer<T> {
     #[inline]
-    pub fn as_ptr(&self, index: usize) -> *const T {
-        assert!(index < self.size);
-        
-        unsafe { self.inner.offset(index as isize) as *const T }
+    fn size(&self) -> usize {
+        self.size
     }
 
     #[inline]
-    pub fn as_mut_ptr(&mut self, index: usize) -> *mut T {
-        assert!(index < self.size as usize);
-        
-        unsafe { self.inner.offset(index as isize) }
+    fn slot(&self, index: usize) -> *mut MaybeUninit<T> {
+        self.inner[index].get()
     }
 
+    #[cfg(loom)]
     #[inline]
-    pub fn write(&mut self, index: usize, value: T) {        
-        unsafe { ptr::write_unaligned(self.as_mut_ptr(index), value) }
+    fn write(&self, index: usize, value: T) {
+        self.shadow[index].with_mut(|_| ());
+        unsafe { ptr::write(self.as_mut_ptr(index), value) }
     }
 
+    #[cfg(loom)]
     #[inline]
-    pub fn read(&self, index: usize) -> T {
-        unsafe { ptr::read_unaligned(self.as_ptr(index)) }
+    unsafe fn read(&self, index: usize) -> T {
+        self.shadow[index].with_mut(|_| ());
+        ptr::read(self.as_ptr(index))
     }
 
+    #[cfg(loom)]
     #[inline]
-    pub fn at(&self, index: usize) -> &T {
-        unsafe { &*self.as_ptr(index) }
+    unsafe fn at(&self, index: usize) -> &T {
+        self.shadow[index].with(|_| ());
+        &*self.as_ptr(index)
     }
 
+    #[cfg(loom)]
     #[inline]
-    pub fn at_mut(&mut self, index: usize) -> &mut T {
-        unsafe { &mut *self.as_mut_ptr(index) }
+    unsafe fn drop_at(&self, index: usize) {
+        self.shadow[index].with_mut(|_| ());
+        ptr::drop_in_place(self.as_mut_ptr(index))
     }
+}
+
+impl<T> Debug for Buffer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Buffer").field("size", &self.size).finish()
+    }
+}
+
+/// Inline storage of `N` slots, needs no allocation and can be built in a
+/// `const` context.
+///
+/// `N` must be a power of two, anything else fails to build:
+///
+/// ```compile_fail,E0080
+/// let _ = ring::ring::Inline::<u8, 3>::new();
+/// ```
+#[repr(align(64))]
+pub struct Inline<T, const N: usize> {
+    inner: UnsafeCell<MaybeUninit<[T; N]>>,
+}
+
+impl<T, const N: usize> Inline<T, N> {
+    const POWER_OF_TWO: () = assert!(N.is_power_of_two(), "size must be a power of two");
 
     #[inline]
-    pub fn drop_at(&mut self, index: usize) {
-        unsafe { self.as_mut_ptr(index).drop_in_place() }
+    pub const fn new() -> Self {
+        let () = Self::POWER_OF_TWO;
+        Inline { inner: UnsafeCell::new(MaybeUninit::uninit()) }
     }
 }
 
-impl<T: Debug> Debug for Buffer<T> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "[ ")?;
-        for i in 0..self.size {
-            write!(f, "{:?} ", self.read(i as usize))?;
-        }
-        write!(f, " ]")
+impl<T, const N: usize> Default for Inline<T, N> {
+    #[inline]
+    fn default() -> Self {
+        Inline::new()
     }
 }
 
-impl<T> Drop for Buffer<T> {
-    #[inline(always)]
-    fn drop(&mut self) {
-        let v = unsafe { Vec::from_raw_parts(self.inner, 0, self.size as usize) };
+unsafe impl<T, const N: usize> Storage<T> for Inline<T, N> {
+    #[inline]
+    fn size(&self) -> usize {
+        N
+    }
+
+    #[inline]
+    fn slot(&self, index: usize) -> *mut MaybeUninit<T> {
+        assert!(index < N);
 
-        drop(v);
+        unsafe { (self.inner.get() as *mut MaybeUninit<T>).add(index) }
+    }
+}
+
+impl<T, const N: usize> Debug for Inline<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Inline").field("size", &N).finish()
     }
 }
 
@@ -100,21 +212,27 @@ mod tests {
 
     #[test]
     fn read_write() {
-        let mut buf : Buffer<u8> = Buffer::new(8);
+        let buf : Buffer<u8> = Buffer::new(8);
         buf.write(0, 127);
         buf.write(7, 128);
 
-        assert_eq!(buf.read(0), 127);
-        assert_eq!(buf.read(7), 128);
+        assert_eq!(unsafe { buf.read(0) }, 127);
+        assert_eq!(unsafe { buf.read(7) }, 128);
     }
 
     #[test]
     #[should_panic]
     fn test_overflow() {
-        let mut buf : Buffer<u8> = Buffer::new(8);
+        let buf : Buffer<u8> = Buffer::new(8);
         buf.write(8, 10);
     }
 
+    #[test]
+    #[should_panic(expected = "power of two")]
+    fn test_not_power_of_two() {
+        let _ : Buffer<u8> = Buffer::new(6);
+    }
+
     static mut N : usize = 0;
 
     struct S { num: usize }
@@ -140,19 +258,59 @@ mod tests {
         drop(s);
         assert_eq!(unsafe { N }, 0);
 
-        let mut buf : Buffer<S> = Buffer::new(9);
+        let buf : Buffer<S> = Buffer::new(8);
         buf.write(0, S::new());
-        assert_eq!(buf.at(0).num, 1);
+        assert_eq!(unsafe { buf.at(0) }.num, 1);
 
         buf.write(1, S::new());
-        assert_eq!(buf.at(1).num, 2);
+        assert_eq!(unsafe { buf.at(1) }.num, 2);
 
         assert_eq!(unsafe { N }, 2);
 
-        buf.drop_at(0);
+        unsafe { buf.drop_at(0) };
         assert_eq!(unsafe { N }, 1);
 
-        buf.drop_at(1);
+        unsafe { buf.drop_at(1) };
         assert_eq!(unsafe { N }, 0);
+
+        // moving a value out hands its drop over to the caller
+        buf.write(2, S::new());
+        let s = unsafe { buf.read(2) };
+        assert_eq!(unsafe { N }, 1);
+        drop(s);
+        assert_eq!(unsafe { N }, 0);
+
+        // the buffer never drops slots itself, live or not
+        buf.write(3, S::new());
+        drop(buf);
+        assert_eq!(unsafe { N }, 1);
+        unsafe { N = 0 };
+    }
+
+    #[test]
+    fn test_debug() {
+        let buf : Buffer<u8> = Buffer::new(4);
+        assert_eq!(format!("{:?}", buf), "Buffer { size: 4 }");
+
+        let buf : Inline<u8, 4> = Inline::new();
+        assert_eq!(format!("{:?}", buf), "Inline { size: 4 }");
+    }
+
+    #[test]
+    fn test_inline() {
+        let buf : Inline<u16, 8> = Inline::new();
+        assert_eq!(buf.size(), 8);
+        buf.write(0, 127);
+        buf.write(7, 128);
+
+        assert_eq!(unsafe { buf.read(0) }, 127);
+        assert_eq!(unsafe { *buf.at(7) }, 128);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_inline_overflow() {
+        let buf : Inline<u8, 8> = Inline::new();
+        buf.write(8, 10);
     }
 }
\ No newline at end of file
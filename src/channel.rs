@@ -1,83 +1,1860 @@
-use std::cell::UnsafeCell;
 use std::sync::Arc;
-use std::sync::atomic::spin_loop_hint;
-use std::fmt::Debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::fmt::{self, Debug};
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::mem::{self, ManuallyDrop};
+use std::ops::{Deref, DerefMut};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
 
-use crate::ring::Ring;
+use crate::ring::{Ring, Bulk, WriteGuard, ReadGuard};
+use crate::stamped::StampedRing;
+use crate::wait::Wakers;
 
-type ChannelRing<T> = Arc<UnsafeCell<Ring<T>>>;
+pub use crate::error::{SendError, RecvError, TrySendError, TryRecvError};
+pub use crate::error::{SendTimeoutError, RecvTimeoutError};
+pub use crate::error::{TrySelectError, SelectTimeoutError};
+pub use crate::select::Select;
+pub use crate::unbounded::{UnboundedSender, UnboundedReceiver, unbounded, unbounded_with};
+pub use crate::wait::{WaitStrategy, BusySpin, Yield, Backoff, Park};
 
+mod sealed {
+    use super::Flavor;
+
+    pub trait Sealed {}
+
+    // what a channel needs from the ring it is built on
+    pub trait Queue<T> {
+        fn with_log2(log2: usize) -> Self;
+
+        // Safety: same as the `Flavor` operations
+        unsafe fn enqueue<F: Flavor>(&self, value: T) -> Option<T>;
+        unsafe fn dequeue<F: Flavor>(&self) -> Option<T>;
+        unsafe fn enqueue_bulk<F: Flavor>(&self, values: &mut Vec<T>) -> usize;
+        unsafe fn dequeue_bulk<F: Flavor>(&self, out: &mut Vec<T>, max: usize) -> usize;
+
+        fn force_enqueue(&self, value: T) -> Option<T>;
+        fn len(&self) -> usize;
+        fn capacity(&self) -> usize;
+        fn remaining(&self) -> usize;
+        fn overwritten(&self) -> usize;
+        fn has_free_slot(&self) -> bool;
+        // whether a dequeue would find an item, not just a claimed slot
+        fn has_item(&self) -> bool;
+    }
+}
+
+/// Selects the ring algorithm used by one side of a channel.
+///
+/// The trait is sealed, `Single` and `Multi` are the only flavours, and its
+/// operations are unsafe: only the channel handles uphold what `Single`
+/// relies on.
+///
+/// ```compile_fail,E0133
+/// use ring::channel::{Flavor, Single};
+/// use ring::ring::Ring;
+///
+/// let ring = Ring::new(2);
+/// <Single as Flavor>::enqueue(&ring, 0u8);
+/// ```
+pub trait Flavor: sealed::Sealed {
+    /// Whether the handles on this side may run concurrently.
+    const MULTI: bool;
+
+    /// # Safety
+    ///
+    /// With a `Single` flavour the caller must guarantee that no other
+    /// thread enqueues into the ring concurrently, as the handle types do.
+    unsafe fn enqueue<T>(ring: &Ring<T>, value: T) -> Option<T>;
+
+    /// # Safety
+    ///
+    /// With a `Single` flavour the caller must guarantee that no other
+    /// thread dequeues from the ring concurrently, as the handle types do.
+    unsafe fn dequeue<T>(ring: &Ring<T>) -> Option<T>;
+
+    /// # Safety
+    ///
+    /// Same as `enqueue`.
+    unsafe fn enqueue_bulk<T>(ring: &Ring<T>, values: &mut Vec<T>) -> usize;
+
+    /// # Safety
+    ///
+    /// Same as `dequeue`.
+    unsafe fn dequeue_bulk<T>(ring: &Ring<T>, out: &mut Vec<T>, max: usize) -> usize;
+}
+
+/// Exactly one handle on this side: the handle can be moved to another
+/// thread but neither cloned nor shared, so the single-threaded ring
+/// operations are safe to use.
+pub struct Single {
+    _marker: PhantomData<Cell<()>>,
+}
+
+/// Any number of handles on this side, each one cloneable.
+pub struct Multi;
+
+impl sealed::Sealed for Single {}
+
+impl Flavor for Single {
+    const MULTI: bool = false;
+
+    #[inline]
+    unsafe fn enqueue<T>(ring: &Ring<T>, value: T) -> Option<T> {
+        unsafe { ring.single_enqueue(value) }
+    }
+
+    #[inline]
+    unsafe fn dequeue<T>(ring: &Ring<T>) -> Option<T> {
+        unsafe { ring.single_dequeue() }
+    }
+
+    #[inline]
+    unsafe fn enqueue_bulk<T>(ring: &Ring<T>, values: &mut Vec<T>) -> usize {
+        unsafe { ring.single_enqueue_bulk(values, Bulk::Partial) }
+    }
+
+    #[inline]
+    unsafe fn dequeue_bulk<T>(ring: &Ring<T>, out: &mut Vec<T>, max: usize) -> usize {
+        unsafe { ring.single_dequeue_bulk(out, max, Bulk::Partial) }
+    }
+}
+
+impl sealed::Sealed for Multi {}
+
+impl Flavor for Multi {
+    const MULTI: bool = true;
+
+    #[inline]
+    unsafe fn enqueue<T>(ring: &Ring<T>, value: T) -> Option<T> {
+        ring.multi_enqueue(value)
+    }
+
+    #[inline]
+    unsafe fn dequeue<T>(ring: &Ring<T>) -> Option<T> {
+        ring.multi_dequeue()
+    }
+
+    #[inline]
+    unsafe fn enqueue_bulk<T>(ring: &Ring<T>, values: &mut Vec<T>) -> usize {
+        ring.multi_enqueue_bulk(values, Bulk::Partial)
+    }
+
+    #[inline]
+    unsafe fn dequeue_bulk<T>(ring: &Ring<T>, out: &mut Vec<T>, max: usize) -> usize {
+        ring.multi_dequeue_bulk(out, max, Bulk::Partial)
+    }
+}
+
+/// Selects the ring a channel is built on, `Ring` unless told otherwise.
+///
+/// The trait is sealed. `Ring` hands slots over through its cursor tails
+/// and lends them out in place, see `Sender::reserve`. `StampedRing` hands
+/// them over with a stamp per slot, so that a thread preempted half way
+/// through an operation holds up no other one, see `ring::stamped`:
+///
+/// ```
+/// use ring::channel::{self, Multi};
+///
+/// let (tx, rx) = channel::stamped::<u8, Multi, Multi>(2);
+/// let tx1 = tx.clone();
+/// tx.send(1).unwrap();
+/// tx1.send(2).unwrap();
+/// assert_eq!(rx.recv(), Ok(1));
+/// assert_eq!(rx.recv(), Ok(2));
+/// ```
+pub trait Backend<T>: sealed::Queue<T> + Debug {}
+
+impl<T> Backend<T> for Ring<T> {}
+
+impl<T> sealed::Queue<T> for Ring<T> {
+    #[inline]
+    fn with_log2(log2: usize) -> Self {
+        Ring::new(log2)
+    }
+
+    #[inline]
+    unsafe fn enqueue<F: Flavor>(&self, value: T) -> Option<T> {
+        unsafe { F::enqueue(self, value) }
+    }
+
+    #[inline]
+    unsafe fn dequeue<F: Flavor>(&self) -> Option<T> {
+        unsafe { F::dequeue(self) }
+    }
+
+    #[inline]
+    unsafe fn enqueue_bulk<F: Flavor>(&self, values: &mut Vec<T>) -> usize {
+        unsafe { F::enqueue_bulk(self, values) }
+    }
+
+    #[inline]
+    unsafe fn dequeue_bulk<F: Flavor>(&self, out: &mut Vec<T>, max: usize) -> usize {
+        unsafe { F::dequeue_bulk(self, out, max) }
+    }
+
+    #[inline]
+    fn force_enqueue(&self, value: T) -> Option<T> {
+        Ring::force_enqueue(self, value)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        Ring::len(self)
+    }
+
+    #[inline]
+    fn capacity(&self) -> usize {
+        Ring::capacity(self)
+    }
+
+    #[inline]
+    fn remaining(&self) -> usize {
+        Ring::remaining(self)
+    }
+
+    #[inline]
+    fn overwritten(&self) -> usize {
+        Ring::overwritten(self)
+    }
+
+    #[inline]
+    fn has_free_slot(&self) -> bool {
+        Ring::has_free_slot(self)
+    }
+
+    #[inline]
+    fn has_item(&self) -> bool {
+        // the length only counts published items
+        !Ring::is_empty(self)
+    }
+}
+
+impl<T> Backend<T> for StampedRing<T> {}
+
+// the stamped ring has no bulk operations of its own: with every slot
+// handed over on its own, a bulk one would claim slots one at a time all
+// the same
+impl<T> sealed::Queue<T> for StampedRing<T> {
+    #[inline]
+    fn with_log2(log2: usize) -> Self {
+        StampedRing::new(log2)
+    }
+
+    #[inline]
+    unsafe fn enqueue<F: Flavor>(&self, value: T) -> Option<T> {
+        if F::MULTI {
+            self.multi_enqueue(value)
+        } else {
+            unsafe { self.single_enqueue(value) }
+        }
+    }
+
+    #[inline]
+    unsafe fn dequeue<F: Flavor>(&self) -> Option<T> {
+        if F::MULTI {
+            self.multi_dequeue()
+        } else {
+            unsafe { self.single_dequeue() }
+        }
+    }
+
+    unsafe fn enqueue_bulk<F: Flavor>(&self, values: &mut Vec<T>) -> usize {
+        let mut iter = mem::take(values).into_iter();
+        let mut count = 0;
+        while let Some(value) = iter.next() {
+            if let Some(moved) = unsafe { self.enqueue::<F>(value) } {
+                // full, the rest goes back in order
+                values.push(moved);
+                values.extend(iter);
+                break;
+            }
+            count += 1;
+        }
+        count
+    }
+
+    unsafe fn dequeue_bulk<F: Flavor>(&self, out: &mut Vec<T>, max: usize) -> usize {
+        let mut count = 0;
+        while count < max {
+            match unsafe { self.dequeue::<F>() } {
+                Some(value) => out.push(value),
+                None => break,
+            }
+            count += 1;
+        }
+        count
+    }
+
+    #[inline]
+    fn force_enqueue(&self, value: T) -> Option<T> {
+        StampedRing::force_enqueue(self, value)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        StampedRing::len(self)
+    }
+
+    #[inline]
+    fn capacity(&self) -> usize {
+        StampedRing::capacity(self)
+    }
+
+    #[inline]
+    fn remaining(&self) -> usize {
+        StampedRing::remaining(self)
+    }
+
+    #[inline]
+    fn overwritten(&self) -> usize {
+        StampedRing::overwritten(self)
+    }
+
+    #[inline]
+    fn has_free_slot(&self) -> bool {
+        StampedRing::has_free_slot(self)
+    }
+
+    #[inline]
+    fn has_item(&self) -> bool {
+        StampedRing::has_item(self)
+    }
+}
+
+struct Shared<T, W, B = Ring<T>> {
+    ring: B,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    // senders wait here while the ring is full
+    not_full: W,
+    // receivers wait here while the ring is empty
+    not_empty: W,
+    // async tasks waiting on the same conditions
+    send_wakers: Wakers,
+    recv_wakers: Wakers,
+    // the items live in `ring`, whose `Sync` is all that matters
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<T, W: WaitStrategy, B: Backend<T>> Shared<T, W, B> {
+    // the ring commits are only release stores, the wakers bring the
+    // fence their handshake needs, see `Wakers`
+
+    #[inline]
+    fn notify_not_full(&self) {
+        self.not_full.notify();
+        self.send_wakers.wake_fenced();
+    }
+
+    #[inline]
+    fn notify_not_empty(&self) {
+        self.not_empty.notify();
+        self.recv_wakers.wake_fenced();
+    }
+
+    // every sender is gone. A sender may have published right before
+    // leaving, so a receiver that sees this still drains once more before
+    // it reports the disconnect
+    #[inline]
+    fn disconnected(&self) -> bool {
+        self.senders.load(Ordering::Acquire) == 0
+    }
+
+    // the operations of one side, shared by its handle and its futures
+    //
+    // Safety: `F` must be the flavour of the side's handle, the caller
+    // holds that handle or a borrow of it, exclusive for `Single`
+
+    #[inline]
+    unsafe fn try_send<F: Flavor>(&self, value: T) -> Result<(), TrySendError<T>> {
+        if self.receivers.load(Ordering::Acquire) == 0 {
+            return Err(TrySendError::Disconnected(value));
+        }
+        match unsafe { self.ring.enqueue::<F>(value) } {
+            Some(moved) => Err(TrySendError::Full(moved)),
+            None => {
+                self.notify_not_empty();
+                Ok(())
+            },
+        }
+    }
+
+    #[inline]
+    unsafe fn try_recv<F: Flavor>(&self) -> Result<T, TryRecvError> {
+        if let Some(value) = unsafe { self.ring.dequeue::<F>() } {
+            self.notify_not_full();
+            return Ok(value);
+        }
+        if self.disconnected() {
+            return match unsafe { self.ring.dequeue::<F>() } {
+                Some(value) => {
+                    self.notify_not_full();
+                    Ok(value)
+                },
+                None => Err(TryRecvError::Disconnected),
+            };
+        }
+        Err(TryRecvError::Empty)
+    }
+
+    // `key` is the registration of the future polling, see `Wakers`
+    unsafe fn poll_send<F: Flavor>(&self, slot: &mut Option<T>, key: &mut Option<usize>, cx: &mut Context<'_>)
+        -> Poll<Result<(), SendError<T>>> {
+        let mut value = slot.take().expect("SendFuture polled after completion");
+        let mut registered = false;
+        loop {
+            match unsafe { self.try_send::<F>(value) } {
+                Ok(()) => return Poll::Ready(Ok(())),
+                Err(TrySendError::Disconnected(moved)) => return Poll::Ready(Err(SendError(moved))),
+                Err(TrySendError::Full(moved)) => value = moved,
+            }
+            if registered {
+                *slot = Some(value);
+                return Poll::Pending;
+            }
+            // register before trying again, a slot freed in between is
+            // then either seen or wakes us
+            self.send_wakers.register_keyed(key, cx.waker());
+            registered = true;
+        }
+    }
+
+    unsafe fn poll_recv<F: Flavor>(&self, key: Option<&mut Option<usize>>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut key = key;
+        let mut registered = false;
+        loop {
+            match unsafe { self.try_recv::<F>() } {
+                Ok(value) => return Poll::Ready(Some(value)),
+                Err(TryRecvError::Disconnected) => return Poll::Ready(None),
+                Err(TryRecvError::Empty) => (),
+            }
+            if registered {
+                return Poll::Pending;
+            }
+            // register before trying again, an item published in between
+            // is then either seen or wakes us
+            match key.as_deref_mut() {
+                Some(key) => self.recv_wakers.register_keyed(key, cx.waker()),
+                None => self.recv_wakers.register(cx.waker()),
+            }
+            registered = true;
+        }
+    }
+
+    fn fmt_handle(&self, name: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct(name)
+            .field("ring", &self.ring)
+            .field("senders", &self.senders.load(Ordering::Relaxed))
+            .field("receivers", &self.receivers.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+/// Sending half of a channel.
+///
+/// It is `Send` for `T: Send`, a payload that cannot leave its thread
+/// cannot go through the channel either:
+///
+/// ```compile_fail,E0277
+/// use std::rc::Rc;
+/// use std::thread;
+///
+/// let (tx, _rx) = ring::channel::channel::<Rc<u8>>(2);
+/// thread::spawn(move || { tx.send(Rc::new(0)).unwrap(); });
+/// ```
+///
+/// A `Single` sender is neither `Sync` nor `Clone`, so there is never more
+/// than one thread sending:
+///
+/// ```compile_fail,E0277
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<ring::channel::Sender<u8, ring::channel::Single>>();
+/// ```
+///
+/// ```compile_fail,E0599
+/// let (tx, _rx) = ring::channel::spsc::<u8>(2);
+/// let _ = tx.clone();
+/// ```
+#[repr(align(64))]
+pub struct Sender<T, P: Flavor = Multi, W: WaitStrategy = BusySpin, B: Backend<T> = Ring<T>> {
+    shared: Arc<Shared<T, W, B>>,
+    _flavor: PhantomData<P>,
+}
+
+/// Receiving half of a channel.
+///
+/// It is `Send` for `T: Send`:
+///
+/// ```compile_fail,E0277
+/// use std::rc::Rc;
+/// use std::thread;
+///
+/// let (_tx, rx) = ring::channel::channel::<Rc<u8>>(2);
+/// thread::spawn(move || { drop(rx.recv()); });
+/// ```
+///
+/// A `Single` receiver is neither `Sync` nor `Clone`, so there is never
+/// more than one thread receiving:
+///
+/// ```compile_fail,E0277
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<ring::channel::Receiver<u8, ring::channel::Single>>();
+/// ```
+///
+/// ```compile_fail,E0599
+/// let (_tx, rx) = ring::channel::spsc::<u8>(2);
+/// let _ = rx.clone();
+/// ```
 #[repr(align(64))]
-pub struct Channel<T> {
-    ring: ChannelRing<T>,
+pub struct Receiver<T, C: Flavor = Multi, W: WaitStrategy = BusySpin, B: Backend<T> = Ring<T>> {
+    shared: Arc<Shared<T, W, B>>,
+    _flavor: PhantomData<C>,
+}
+
+/// Sending half of a channel with `Multi` receivers, which can also evict
+/// the oldest item instead of waiting, see `overwriting`. Everything else
+/// goes through the `Sender` it derefs to.
+///
+/// Eviction dequeues on the sender's thread, so a plain `Sender`, whose
+/// receiver may be `Single`, cannot do it:
+///
+/// ```compile_fail,E0599
+/// let (tx, _rx) = ring::channel::spsc::<u8>(2);
+/// let _ = tx.force_send(0);
+/// ```
+pub struct OverwritingSender<T, P: Flavor = Multi, W: WaitStrategy = BusySpin, B: Backend<T> = Ring<T>> {
+    inner: Sender<T, P, W, B>,
+}
+
+impl<T, P: Flavor, W: WaitStrategy, B: Backend<T>> Sender<T, P, W, B> {
+    #[inline]
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.send_until(value, None).map_err(|err| SendError(err.into_inner()))
+    }
+
+    #[inline]
+    pub fn send_timeout(&self, value: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.send_until(value, Some(deadline)),
+            None => self.send_until(value, None),
+        }
+    }
+
+    #[inline]
+    pub fn send_deadline(&self, value: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        self.send_until(value, Some(deadline))
+    }
+
+    fn send_until(&self, v: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        let mut value = v;
+        let mut attempt = 0;
+        loop {
+            let token = self.shared.not_full.prepare();
+            match self.try_send(value) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(moved)) => value = moved,
+                Err(TrySendError::Disconnected(moved)) => {
+                    return Err(SendTimeoutError::Disconnected(moved))
+                },
+            }
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    return Err(SendTimeoutError::Timeout(value));
+                }
+            }
+            self.shared.not_full.wait(token, attempt, deadline);
+            attempt = attempt.saturating_add(1);
+        }
+    }
+
+    /// Sends every item, moving as many as fit into the ring at a time.
+    /// On disconnection the unsent items are handed back.
+    ///
+    /// Items are taken from the iterator before the slots they go to are
+    /// claimed, so an iterator that panics or ends early never leaves the
+    /// ring with a claimed slot nobody writes.
+    pub fn send_many<I>(&self, values: I) -> Result<(), SendError<Vec<T>>>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let capacity = self.shared.ring.capacity();
+        let mut iter = values.into_iter().fuse();
+        let mut staged = Vec::new();
+        let mut attempt = 0;
+        loop {
+            staged.extend(iter.by_ref().take(capacity - staged.len()));
+            if staged.is_empty() {
+                return Ok(());
+            }
+            let token = self.shared.not_full.prepare();
+            if self.shared.receivers.load(Ordering::Acquire) == 0 {
+                staged.extend(iter);
+                return Err(SendError(staged));
+            }
+            if self.enqueue_bulk(&mut staged) > 0 {
+                self.shared.notify_not_empty();
+                attempt = 0;
+                continue;
+            }
+            self.shared.not_full.wait(token, attempt, None);
+            attempt = attempt.saturating_add(1);
+        }
+    }
+
+    #[inline]
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        unsafe { self.shared.try_send::<P>(value) }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.shared.ring.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.shared.ring.capacity()
+    }
+
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        self.shared.ring.remaining()
+    }
+
+    #[inline]
+    pub fn overwritten(&self) -> usize {
+        self.shared.ring.overwritten()
+    }
+
+    // a send would not block: a slot is free or every receiver is gone
+    #[inline]
+    pub(crate) fn is_ready(&self) -> bool {
+        self.shared.receivers.load(Ordering::Acquire) == 0 || self.shared.ring.has_free_slot()
+    }
+
+    #[inline]
+    pub(crate) fn register(&self, waker: &Waker) {
+        self.shared.send_wakers.register(waker);
+    }
+
+    #[inline]
+    pub(crate) fn deregister(&self, waker: &Waker) {
+        self.shared.send_wakers.deregister(waker);
+    }
+
+    // a `Single` sender is neither `Clone` nor `Sync`, so it is the only
+    // thread enqueueing
+    #[inline]
+    fn enqueue_bulk(&self, values: &mut Vec<T>) -> usize {
+        unsafe { self.shared.ring.enqueue_bulk::<P>(values) }
+    }
+}
+
+impl<T, C: Flavor, W: WaitStrategy, B: Backend<T>> Receiver<T, C, W, B> {
+    #[inline]
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.recv_until(None).map_err(|_| RecvError)
+    }
+
+    #[inline]
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.recv_until(Some(deadline)),
+            None => self.recv_until(None),
+        }
+    }
+
+    #[inline]
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(deadline))
+    }
+
+    fn recv_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let mut attempt = 0;
+        loop {
+            let token = self.shared.not_empty.prepare();
+            match self.try_recv() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Empty) => (),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+            }
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    return Err(RecvTimeoutError::Timeout);
+                }
+            }
+            self.shared.not_empty.wait(token, attempt, deadline);
+            attempt = attempt.saturating_add(1);
+        }
+    }
+
+    /// Polls for the next item like a `Stream`: `Ready(None)` once every
+    /// sender is gone and the ring is drained. On `Pending` the task is
+    /// woken by the next send or disconnection.
+    pub fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        unsafe { self.shared.poll_recv::<C>(None, cx) }
+    }
+
+    /// Blocks until at least one item is available, then moves up to `max`
+    /// items into `out` at once. Returns how many were received.
+    pub fn recv_many(&self, out: &mut Vec<T>, max: usize) -> Result<usize, RecvError> {
+        if max == 0 {
+            return Ok(0);
+        }
+        let mut attempt = 0;
+        loop {
+            let token = self.shared.not_empty.prepare();
+            let n = self.dequeue_bulk(out, max);
+            if n > 0 {
+                self.shared.notify_not_full();
+                return Ok(n);
+            }
+            if self.shared.disconnected() {
+                let n = self.dequeue_bulk(out, max);
+                if n > 0 {
+                    self.shared.notify_not_full();
+                    return Ok(n);
+                }
+                return Err(RecvError);
+            }
+            self.shared.not_empty.wait(token, attempt, None);
+            attempt = attempt.saturating_add(1);
+        }
+    }
+
+    #[inline]
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        unsafe { self.shared.try_recv::<C>() }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.shared.ring.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.shared.ring.capacity()
+    }
+
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        self.shared.ring.remaining()
+    }
+
+    #[inline]
+    pub fn overwritten(&self) -> usize {
+        self.shared.ring.overwritten()
+    }
+
+    // a receive would not block: an item is available or every sender is
+    // gone
+    #[inline]
+    pub(crate) fn is_ready(&self) -> bool {
+        self.shared.ring.has_item() || self.shared.disconnected()
+    }
+
+    #[inline]
+    pub(crate) fn register(&self, waker: &Waker) {
+        self.shared.recv_wakers.register(waker);
+    }
+
+    #[inline]
+    pub(crate) fn deregister(&self, waker: &Waker) {
+        self.shared.recv_wakers.deregister(waker);
+    }
+
+    // a `Single` receiver is neither `Clone` nor `Sync`, so it is the only
+    // thread dequeueing
+    #[inline]
+    fn dequeue_bulk(&self, out: &mut Vec<T>, max: usize) -> usize {
+        unsafe { self.shared.ring.dequeue_bulk::<C>(out, max) }
+    }
+}
+
+impl<T, W: WaitStrategy, B: Backend<T>> Sender<T, Multi, W, B> {
+    /// Sends without blocking the thread: while the ring is full the task
+    /// is parked and woken once a receiver frees a slot.
+    #[inline]
+    pub fn send_async(&self, value: T) -> SendFuture<'_, T, Multi, W, B> {
+        SendFuture { shared: &self.shared, value: Some(value), key: None, _sender: PhantomData }
+    }
+}
+
+impl<T, W: WaitStrategy, B: Backend<T>> Sender<T, Single, W, B> {
+    /// Same as for a `Multi` sender, but the future borrows the sender
+    /// exclusively so that it is `Send` and the task can move between
+    /// threads:
+    ///
+    /// ```
+    /// fn assert_send<T: Send>(_: &T) {}
+    ///
+    /// let (mut tx, _rx) = ring::channel::spsc::<u8>(2);
+    /// assert_send(&tx.send_async(0));
+    /// ```
+    #[inline]
+    pub fn send_async(&mut self, value: T) -> SendFuture<'_, T, Single, W, B> {
+        SendFuture { shared: &self.shared, value: Some(value), key: None, _sender: PhantomData }
+    }
+}
+
+impl<T, W: WaitStrategy> Sender<T, Single, W> {
+    /// Blocks until a slot is free and lends it to be built in place, see
+    /// `WriteGuard`. Taking `&mut self` keeps any other send out while the
+    /// guard is alive.
+    pub fn reserve(&mut self) -> Result<SendGuard<'_, T, W>, SendError<()>> {
+        let mut attempt = 0;
+        loop {
+            let token = self.shared.not_full.prepare();
+            if self.shared.receivers.load(Ordering::Acquire) == 0 {
+                return Err(SendError(()));
+            }
+            // only this sender fills slots, a free one stays free
+            if unsafe { self.shared.ring.single_reserve() }.is_some() {
+                break;
+            }
+            self.shared.not_full.wait(token, attempt, None);
+            attempt = attempt.saturating_add(1);
+        }
+        self.try_reserve().map_err(|_| SendError(()))
+    }
+
+    #[inline]
+    pub fn try_reserve(&mut self) -> Result<SendGuard<'_, T, W>, TrySendError<()>> {
+        if self.shared.receivers.load(Ordering::Acquire) == 0 {
+            return Err(TrySendError::Disconnected(()));
+        }
+        match unsafe { self.shared.ring.single_reserve() } {
+            Some(inner) => Ok(SendGuard { inner: ManuallyDrop::new(inner), shared: &self.shared }),
+            None => Err(TrySendError::Full(())),
+        }
+    }
+}
+
+impl<T, W: WaitStrategy, B: Backend<T>> Receiver<T, Multi, W, B> {
+    /// Receives without blocking the thread: while the ring is empty the
+    /// task is parked and woken once a sender publishes.
+    #[inline]
+    pub fn recv_async(&self) -> RecvFuture<'_, T, Multi, W, B> {
+        RecvFuture { shared: &self.shared, key: None, _receiver: PhantomData }
+    }
+}
+
+impl<T, W: WaitStrategy, B: Backend<T>> Receiver<T, Single, W, B> {
+    /// Same as for a `Multi` receiver, but the future borrows the receiver
+    /// exclusively so that it is `Send` and the task can move between
+    /// threads:
+    ///
+    /// ```
+    /// fn assert_send<T: Send>(_: &T) {}
+    ///
+    /// let (_tx, mut rx) = ring::channel::spsc::<u8>(2);
+    /// assert_send(&rx.recv_async());
+    /// ```
+    #[inline]
+    pub fn recv_async(&mut self) -> RecvFuture<'_, T, Single, W, B> {
+        RecvFuture { shared: &self.shared, key: None, _receiver: PhantomData }
+    }
+}
+
+impl<T, W: WaitStrategy> Receiver<T, Single, W> {
+    /// Blocks until an item is available and lends it in place, see
+    /// `ReadGuard`. Taking `&mut self` keeps any other receive out while
+    /// the guard is alive.
+    pub fn read_guard(&mut self) -> Result<RecvGuard<'_, T, W>, RecvError> {
+        let mut attempt = 0;
+        loop {
+            let token = self.shared.not_empty.prepare();
+            // only this receiver drains slots, an available item stays
+            if !self.shared.ring.is_empty() {
+                break;
+            }
+            if self.shared.disconnected() {
+                if self.shared.ring.is_empty() {
+                    return Err(RecvError);
+                }
+                break;
+            }
+            self.shared.not_empty.wait(token, attempt, None);
+            attempt = attempt.saturating_add(1);
+        }
+        self.try_read_guard().map_err(|_| RecvError)
+    }
+
+    #[inline]
+    pub fn try_read_guard(&mut self) -> Result<RecvGuard<'_, T, W>, TryRecvError> {
+        let shared = &self.shared;
+        let mut guard = unsafe { shared.ring.single_read_guard() };
+        if guard.is_none() {
+            if !shared.disconnected() {
+                return Err(TryRecvError::Empty);
+            }
+            guard = unsafe { shared.ring.single_read_guard() };
+        }
+        match guard {
+            Some(inner) => Ok(RecvGuard { inner: ManuallyDrop::new(inner), shared }),
+            None => Err(TryRecvError::Disconnected),
+        }
+    }
+}
+
+impl<T, W: WaitStrategy, B: Backend<T>> Clone for Sender<T, Multi, W, B> {
+    #[inline]
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::AcqRel);
+        Sender{ shared: Arc::clone(&self.shared), _flavor: PhantomData }
+    }
+}
+
+impl<T, P: Flavor, W: WaitStrategy, B: Backend<T>> OverwritingSender<T, P, W, B> {
+    /// Sends without ever waiting: when the ring is full the oldest item is
+    /// evicted and handed back, see `Ring::force_enqueue`.
+    pub fn force_send(&self, value: T) -> Result<Option<T>, SendError<T>> {
+        let shared = &self.inner.shared;
+        if shared.receivers.load(Ordering::Acquire) == 0 {
+            return Err(SendError(value));
+        }
+        let evicted = shared.ring.force_enqueue(value);
+        shared.notify_not_empty();
+        Ok(evicted)
+    }
+}
+
+impl<T, P: Flavor, W: WaitStrategy, B: Backend<T>> Deref for OverwritingSender<T, P, W, B> {
+    type Target = Sender<T, P, W, B>;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T, P: Flavor, W: WaitStrategy, B: Backend<T>> DerefMut for OverwritingSender<T, P, W, B> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl<T, W: WaitStrategy, B: Backend<T>> Clone for OverwritingSender<T, Multi, W, B> {
+    #[inline]
+    fn clone(&self) -> Self {
+        OverwritingSender { inner: self.inner.clone() }
+    }
+}
+
+impl<T, P: Flavor, W: WaitStrategy, B: Backend<T>> Debug for OverwritingSender<T, P, W, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.shared.fmt_handle("OverwritingSender", f)
+    }
 }
 
-impl<T: Debug> Channel<T> {
+impl<T, W: WaitStrategy, B: Backend<T>> Clone for Receiver<T, Multi, W, B> {
     #[inline]
-    pub fn new(log2: usize) -> Self {
-        let ring = Arc::new(UnsafeCell::new(Ring::new(log2)));
-        Channel { ring }
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::AcqRel);
+        Receiver{ shared: Arc::clone(&self.shared), _flavor: PhantomData }
     }
-    
+}
+
+impl<T, P: Flavor, W: WaitStrategy, B: Backend<T>> Drop for Sender<T, P, W, B> {
     #[inline]
-    pub fn send(&self, v: T) {
-        let mut value = v;
-        loop {
-            match self.enqueue(value) {
-                Some(moved) => value = moved,
-                None => break,
-            }
-            spin_loop_hint();
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.notify_not_empty();
         }
     }
+}
+
+impl<T, P: Flavor, W: WaitStrategy, B: Backend<T>> Debug for Sender<T, P, W, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.shared.fmt_handle("Sender", f)
+    }
+}
 
+impl<T, C: Flavor, W: WaitStrategy, B: Backend<T>> Debug for Receiver<T, C, W, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.shared.fmt_handle("Receiver", f)
+    }
+}
+
+impl<T, C: Flavor, W: WaitStrategy, B: Backend<T>> Drop for Receiver<T, C, W, B> {
     #[inline]
-    pub fn recv(&self) -> T {
-        loop {
-            if let Some(value) = self.dequeue() {
-                break value;
-            }
-            spin_loop_hint();
+    fn drop(&mut self) {
+        if self.shared.receivers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.notify_not_full();
         }
     }
+}
+
+/// A slot lent by `Sender::reserve`, derefs to the ring's `WriteGuard`.
+/// Publishing it wakes up the receiver.
+pub struct SendGuard<'a, T, W: WaitStrategy> {
+    inner: ManuallyDrop<WriteGuard<'a, T>>,
+    shared: &'a Shared<T, W>,
+}
+
+impl<'a, T, W: WaitStrategy> SendGuard<'a, T, W> {
+    /// Publishes the slot, or gives it back if it was never initialised.
+    #[inline]
+    pub fn commit(self) {}
+}
+
+impl<'a, T, W: WaitStrategy> Deref for SendGuard<'a, T, W> {
+    type Target = WriteGuard<'a, T>;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
 
+impl<'a, T, W: WaitStrategy> DerefMut for SendGuard<'a, T, W> {
     #[inline]
-    pub fn clone(&self) -> Self {
-        Channel{ ring: Arc::clone(&self.ring) }
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
     }
+}
 
+impl<'a, T, W: WaitStrategy> Drop for SendGuard<'a, T, W> {
     #[inline]
-    fn enqueue(&self, value: T) -> Option<T> {
-        if Arc::strong_count(&self.ring) > 2 {
-            unsafe { (*self.ring.get()).multi_enqueue(value) }
-        } else {
-            unsafe { (*self.ring.get()).single_enqueue(value) }
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(&mut self.inner) };
+        self.shared.notify_not_empty();
+    }
+}
+
+/// An item lent by `Receiver::read_guard`, derefs to the item itself.
+/// Releasing it wakes up the sender.
+///
+/// Like the item it lends out, it is only `Sync` when `T` is:
+///
+/// ```compile_fail,E0277
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<ring::channel::RecvGuard<'static, std::cell::Cell<u8>, ring::channel::BusySpin>>();
+/// ```
+pub struct RecvGuard<'a, T, W: WaitStrategy> {
+    inner: ManuallyDrop<ReadGuard<'a, T>>,
+    shared: &'a Shared<T, W>,
+}
+
+impl<'a, T, W: WaitStrategy> RecvGuard<'a, T, W> {
+    /// Moves the item out and releases the slot.
+    #[inline]
+    pub fn take(mut self) -> T {
+        let inner = unsafe { ManuallyDrop::take(&mut self.inner) };
+        let shared = self.shared;
+        mem::forget(self);
+
+        let value = inner.take();
+        shared.notify_not_full();
+        value
+    }
+}
+
+impl<'a, T, W: WaitStrategy> Deref for RecvGuard<'a, T, W> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<'a, T, W: WaitStrategy> Drop for RecvGuard<'a, T, W> {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(&mut self.inner) };
+        self.shared.notify_not_full();
+    }
+}
+
+/// Future returned by `Sender::send_async`.
+#[must_use = "futures do nothing unless polled"]
+pub struct SendFuture<'a, T, P: Flavor, W: WaitStrategy, B: Backend<T> = Ring<T>> {
+    shared: &'a Shared<T, W, B>,
+    value: Option<T>,
+    // the entry among the send wakers, once registered
+    key: Option<usize>,
+    // the borrow of the sender, exclusive for a `Single` one
+    _sender: PhantomData<&'a mut Sender<T, P, W, B>>,
+}
+
+// the item is only ever moved, never pinned
+impl<'a, T, P: Flavor, W: WaitStrategy, B: Backend<T>> Unpin for SendFuture<'a, T, P, W, B> {}
+
+impl<'a, T, P: Flavor, W: WaitStrategy, B: Backend<T>> Future for SendFuture<'a, T, P, W, B> {
+    type Output = Result<(), SendError<T>>;
+
+    #[inline]
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        unsafe { this.shared.poll_send::<P>(&mut this.value, &mut this.key, cx) }
+    }
+}
+
+// a future dropped while pending, cancelled by a timeout or a lost race,
+// must not leave its waker behind
+impl<'a, T, P: Flavor, W: WaitStrategy, B: Backend<T>> Drop for SendFuture<'a, T, P, W, B> {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            self.shared.send_wakers.deregister_keyed(key);
         }
     }
+}
+
+/// Future returned by `Receiver::recv_async`.
+#[must_use = "futures do nothing unless polled"]
+pub struct RecvFuture<'a, T, C: Flavor, W: WaitStrategy, B: Backend<T> = Ring<T>> {
+    shared: &'a Shared<T, W, B>,
+    // the entry among the receive wakers, once registered
+    key: Option<usize>,
+    // the borrow of the receiver, exclusive for a `Single` one
+    _receiver: PhantomData<&'a mut Receiver<T, C, W, B>>,
+}
+
+impl<'a, T, C: Flavor, W: WaitStrategy, B: Backend<T>> Future for RecvFuture<'a, T, C, W, B> {
+    type Output = Result<T, RecvError>;
 
     #[inline]
-    fn dequeue(&self) -> Option<T> {
-        if Arc::strong_count(&self.ring) > 2 {
-            unsafe { (*self.ring.get()).multi_dequeue() }
-        } else {
-            unsafe { (*self.ring.get()).single_dequeue() }
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        unsafe { this.shared.poll_recv::<C>(Some(&mut this.key), cx) }.map(|value| value.ok_or(RecvError))
+    }
+}
+
+impl<'a, T, C: Flavor, W: WaitStrategy, B: Backend<T>> Drop for RecvFuture<'a, T, C, W, B> {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            self.shared.recv_wakers.deregister_keyed(key);
         }
     }
 }
 
-pub fn channel<T: Debug>(log2: usize) -> (Channel<T>, Channel<T>) {
-    let chan = Channel::new(log2);
-    (chan.clone(), chan)
+#[allow(clippy::type_complexity)]
+fn with_flavors<T, P, C, W, B>(log2: usize, wait: W) -> (Sender<T, P, W, B>, Receiver<T, C, W, B>)
+where
+    P: Flavor,
+    C: Flavor,
+    W: WaitStrategy + Clone,
+    B: Backend<T>,
+{
+    let shared = Arc::new(Shared {
+        ring: B::with_log2(log2),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+        not_full: wait.clone(),
+        not_empty: wait,
+        send_wakers: Wakers::default(),
+        recv_wakers: Wakers::default(),
+        _item: PhantomData,
+    });
+
+    let sender = Sender{ shared: Arc::clone(&shared), _flavor: PhantomData };
+    let receiver = Receiver{ shared, _flavor: PhantomData };
+    (sender, receiver)
+}
+
+/// Single producer, single consumer: neither handle can be cloned.
+pub fn spsc<T>(log2: usize) -> (Sender<T, Single>, Receiver<T, Single>) {
+    spsc_with(log2, BusySpin)
+}
+
+/// Multiple producers, single consumer: only the sender can be cloned.
+pub fn mpsc<T>(log2: usize) -> (Sender<T, Multi>, Receiver<T, Single>) {
+    mpsc_with(log2, BusySpin)
+}
+
+/// Single producer, multiple consumers: only the receiver can be cloned.
+pub fn spmc<T>(log2: usize) -> (Sender<T, Single>, Receiver<T, Multi>) {
+    spmc_with(log2, BusySpin)
+}
+
+/// Multiple producers, multiple consumers: both handles can be cloned.
+pub fn mpmc<T>(log2: usize) -> (Sender<T, Multi>, Receiver<T, Multi>) {
+    mpmc_with(log2, BusySpin)
+}
+
+/// Like `spsc`, blocking operations wait with `wait`.
+pub fn spsc_with<T, W: WaitStrategy + Clone>(log2: usize, wait: W)
+    -> (Sender<T, Single, W>, Receiver<T, Single, W>) {
+    with_flavors(log2, wait)
+}
+
+/// Like `mpsc`, blocking operations wait with `wait`.
+pub fn mpsc_with<T, W: WaitStrategy + Clone>(log2: usize, wait: W)
+    -> (Sender<T, Multi, W>, Receiver<T, Single, W>) {
+    with_flavors(log2, wait)
+}
+
+/// Like `spmc`, blocking operations wait with `wait`.
+pub fn spmc_with<T, W: WaitStrategy + Clone>(log2: usize, wait: W)
+    -> (Sender<T, Single, W>, Receiver<T, Multi, W>) {
+    with_flavors(log2, wait)
+}
+
+/// Like `mpmc`, blocking operations wait with `wait`.
+pub fn mpmc_with<T, W: WaitStrategy + Clone>(log2: usize, wait: W)
+    -> (Sender<T, Multi, W>, Receiver<T, Multi, W>) {
+    with_flavors(log2, wait)
+}
+
+/// Sending side of a channel built on a `StampedRing`.
+pub type StampedSender<T, P = Multi, W = BusySpin> = Sender<T, P, W, StampedRing<T>>;
+
+/// Receiving side of a channel built on a `StampedRing`.
+pub type StampedReceiver<T, C = Multi, W = BusySpin> = Receiver<T, C, W, StampedRing<T>>;
+
+/// Like the constructors above for any pair of flavours, on a
+/// `StampedRing`, see `Backend`.
+pub fn stamped<T, P: Flavor, C: Flavor>(log2: usize)
+    -> (StampedSender<T, P>, StampedReceiver<T, C>) {
+    stamped_with(log2, BusySpin)
+}
+
+/// Like `stamped`, blocking operations wait with `wait`.
+pub fn stamped_with<T, P: Flavor, C: Flavor, W: WaitStrategy + Clone>(log2: usize, wait: W)
+    -> (StampedSender<T, P, W>, StampedReceiver<T, C, W>) {
+    with_flavors(log2, wait)
+}
+
+/// Receivers that can be cloned and a sender that can evict the oldest
+/// item, see `OverwritingSender`.
+pub fn overwriting<T, P: Flavor>(log2: usize) -> (OverwritingSender<T, P>, Receiver<T, Multi>) {
+    overwriting_with(log2, BusySpin)
+}
+
+/// Like `overwriting`, blocking operations wait with `wait`.
+pub fn overwriting_with<T, P: Flavor, W: WaitStrategy + Clone>(log2: usize, wait: W)
+    -> (OverwritingSender<T, P, W>, Receiver<T, Multi, W>) {
+    let (inner, receiver) = with_flavors(log2, wait);
+    (OverwritingSender { inner }, receiver)
+}
+
+/// Like `overwriting`, on a `StampedRing`.
+pub fn stamped_overwriting<T, P: Flavor>(log2: usize)
+    -> (OverwritingSender<T, P, BusySpin, StampedRing<T>>, StampedReceiver<T>) {
+    stamped_overwriting_with(log2, BusySpin)
 }
 
-unsafe impl<T> Sync for Channel<T> {}
-unsafe impl<T> Send for Channel<T> {}
+/// Like `stamped_overwriting`, blocking operations wait with `wait`.
+#[allow(clippy::type_complexity)]
+pub fn stamped_overwriting_with<T, P: Flavor, W: WaitStrategy + Clone>(log2: usize, wait: W)
+    -> (OverwritingSender<T, P, W, StampedRing<T>>, StampedReceiver<T, Multi, W>) {
+    let (inner, receiver) = with_flavors(log2, wait);
+    (OverwritingSender { inner }, receiver)
+}
+
+#[inline]
+pub fn channel<T>(log2: usize) -> (Sender<T>, Receiver<T>) {
+    mpmc(log2)
+}
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use std::panic;
+    use std::thread;
+    use std::task::{Wake, Waker};
+    use super::*;
 
     #[test]
     fn constructor() {
+        let (tx, rx) = channel::<u8>(2);
+        assert_eq!(tx.shared.senders.load(Ordering::Acquire), 1);
+        assert_eq!(rx.shared.receivers.load(Ordering::Acquire), 1);
+    }
+
+    #[test]
+    fn test_debug() {
+        struct Opaque(u8);
+
+        let (tx, rx) = spsc::<Opaque>(2);
+        tx.send(Opaque(7)).unwrap();
+
+        let debug = format!("{:?}", tx);
+        assert!(debug.starts_with("Sender { ring: Ring {"));
+        assert!(debug.contains("len: 1, capacity: 4"));
+        assert!(debug.ends_with("senders: 1, receivers: 1 }"));
+        assert!(format!("{:?}", rx).starts_with("Receiver {"));
+        assert_eq!(rx.recv().map(|v| v.0).ok(), Some(7));
+    }
+
+    #[test]
+    fn test_auto_traits() {
+        fn assert_send<T: Send>() {}
+        fn assert_sync<T: Sync>() {}
+
+        assert_send::<Sender<Vec<u8>, Single>>();
+        assert_send::<Receiver<Vec<u8>, Single>>();
+        assert_send::<Sender<Vec<u8>, Multi, Park>>();
+        assert_send::<Receiver<Vec<u8>, Multi, Park>>();
+        assert_sync::<Sender<Vec<u8>, Multi>>();
+        assert_sync::<Receiver<Vec<u8>, Multi>>();
+        // a payload that is Send but not Sync still crosses threads
+        assert_sync::<Sender<std::cell::Cell<u8>, Multi>>();
+        assert_sync::<Ring<std::cell::Cell<u8>>>();
+    }
+
+    #[test]
+    fn test_clone_counts() {
+        let (tx, rx) = channel::<u8>(2);
+
+        let tx1 = tx.clone();
+        assert_eq!(tx.shared.senders.load(Ordering::Acquire), 2);
+        assert_eq!(rx.shared.receivers.load(Ordering::Acquire), 1);
+
+        let rx1 = rx.clone();
+        assert_eq!(rx.shared.receivers.load(Ordering::Acquire), 2);
+
+        drop(tx1);
+        drop(rx1);
+        assert_eq!(tx.shared.senders.load(Ordering::Acquire), 1);
+        assert_eq!(rx.shared.receivers.load(Ordering::Acquire), 1);
+    }
+
+    #[test]
+    fn test_send_recv() {
+        let (tx, rx) = channel::<u64>(10);
+
+        let handle = thread::spawn(move || {
+            for i in 0..1_000 {
+                tx.send(i).unwrap();
+            }
+        });
+
+        let sum : u64 = (0..1_000).map(|_| rx.recv().unwrap()).sum();
+        handle.join().unwrap();
+
+        assert_eq!(sum, (0..1_000).sum());
+    }
+
+    #[test]
+    fn test_flavors() {
+        const N : u64 = 1_000;
+
+        let (tx, rx) = spsc::<u64>(10);
+        let handle = thread::spawn(move || (0..N).for_each(|i| tx.send(i).unwrap()));
+        assert_eq!((0..N).map(|_| rx.recv().unwrap()).sum::<u64>(), (0..N).sum());
+        handle.join().unwrap();
+
+        let (tx, rx) = mpsc::<u64>(10);
+        let handles : Vec<_> = (0..2).map(|_| {
+            let tx = tx.clone();
+            thread::spawn(move || (0..N).for_each(|i| tx.send(i).unwrap()))
+        }).collect();
+        assert_eq!((0..2*N).map(|_| rx.recv().unwrap()).sum::<u64>(), 2*(0..N).sum::<u64>());
+        handles.into_iter().for_each(|h| h.join().unwrap());
+
+        let (tx, rx) = spmc::<u64>(11);
+        (0..2*N).for_each(|i| tx.send(i).unwrap());
+        let handles : Vec<_> = (0..2).map(|_| {
+            let rx = rx.clone();
+            thread::spawn(move || (0..N).map(|_| rx.recv().unwrap()).sum::<u64>())
+        }).collect();
+        let sum : u64 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(sum, (0..2*N).sum());
+
+        let (tx, rx) = mpmc::<u64>(10);
+        let prods : Vec<_> = (0..2).map(|_| {
+            let tx = tx.clone();
+            thread::spawn(move || (0..N).for_each(|i| tx.send(i).unwrap()))
+        }).collect();
+        let cons : Vec<_> = (0..2).map(|_| {
+            let rx = rx.clone();
+            thread::spawn(move || (0..N).map(|_| rx.recv().unwrap()).sum::<u64>())
+        }).collect();
+        prods.into_iter().for_each(|h| h.join().unwrap());
+        let sum : u64 = cons.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(sum, 2*(0..N).sum::<u64>());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_stamped_flavors() {
+        const N : u64 = 1_000;
+
+        let (tx, rx) = stamped::<u64, Single, Single>(2);
+        let handle = thread::spawn(move || (0..N).for_each(|i| tx.send(i).unwrap()));
+        assert_eq!((0..N).map(|_| rx.recv().unwrap()).sum::<u64>(), (0..N).sum());
+        handle.join().unwrap();
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+
+        let (tx, rx) = stamped_with::<u64, Multi, Multi, _>(3, Park::new());
+        let prods : Vec<_> = (0..2).map(|_| {
+            let tx = tx.clone();
+            thread::spawn(move || (0..N).for_each(|i| tx.send(i).unwrap()))
+        }).collect();
+        let cons : Vec<_> = (0..2).map(|_| {
+            let rx = rx.clone();
+            thread::spawn(move || (0..N).map(|_| rx.recv().unwrap()).sum::<u64>())
+        }).collect();
+        prods.into_iter().for_each(|h| h.join().unwrap());
+        let sum : u64 = cons.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(sum, 2*(0..N).sum::<u64>());
+
+        let (tx, rx) = stamped_overwriting::<u8, Single>(2);
+        assert!(tx.send_many(0..4).is_ok());
+        assert_eq!(tx.try_send(4), Err(TrySendError::Full(4)));
+        assert_eq!(tx.force_send(4), Ok(Some(0)));
+        assert_eq!(rx.overwritten(), 1);
+        let mut out = Vec::new();
+        assert_eq!(rx.recv_many(&mut out, 8), Ok(4));
+        assert_eq!(out, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (tx, rx) = spsc::<u8>(2);
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        drop(tx);
+
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.recv(), Ok(2));
+        assert_eq!(rx.recv(), Err(RecvError));
+
+        let (tx, rx) = mpmc::<u8>(2);
+        let tx1 = tx.clone();
+        drop(rx);
+        assert_eq!(tx.send(1), Err(SendError(1)));
+        assert_eq!(tx1.send(2), Err(SendError(2)));
+    }
+
+    #[test]
+    fn test_disconnect_wakes_receiver() {
+        let (tx, rx) = mpsc::<u8>(2);
+        let handles : Vec<_> = (0..2).map(|i| {
+            let tx = tx.clone();
+            thread::spawn(move || tx.send(i).unwrap())
+        }).collect();
+        drop(tx);
+
+        let mut received = vec![rx.recv().unwrap(), rx.recv().unwrap()];
+        received.sort_unstable();
+        assert_eq!(received, vec![0, 1]);
+        assert_eq!(rx.recv(), Err(RecvError));
+
+        handles.into_iter().for_each(|h| h.join().unwrap());
+    }
+
+    #[test]
+    fn test_try_send_recv() {
+        let (tx, rx) = spsc::<u8>(2);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+
+        assert_eq!(tx.try_send(0), Ok(()));
+        assert_eq!(tx.try_send(1), Ok(()));
+        assert_eq!(tx.try_send(2), Ok(()));
+        assert_eq!(tx.try_send(3), Ok(()));
+        assert_eq!(tx.try_send(4), Err(TrySendError::Full(4)));
+
+        assert_eq!(rx.try_recv(), Ok(0));
+        assert_eq!(tx.try_send(4), Ok(()));
+        drop(tx);
+
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert_eq!(rx.try_recv(), Ok(3));
+        assert_eq!(rx.try_recv(), Ok(4));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+
+        let (tx, rx) = mpsc::<u8>(2);
+        drop(rx);
+        assert_eq!(tx.try_send(0), Err(TrySendError::Disconnected(0)));
+    }
+
+    macro_rules! wait_test {
+        ($wait:expr) => {{
+            const N : u64 = 1_000;
+
+            let (tx, rx) = spsc_with::<u64, _>(2, $wait);
+            let handle = thread::spawn(move || (0..N).for_each(|i| tx.send(i).unwrap()));
+            assert_eq!((0..N).map(|_| rx.recv().unwrap()).sum::<u64>(), (0..N).sum());
+            assert_eq!(rx.recv(), Err(RecvError));
+            handle.join().unwrap();
+
+            let (tx, rx) = mpmc_with::<u64, _>(2, $wait);
+            let prods : Vec<_> = (0..2).map(|_| {
+                let tx = tx.clone();
+                thread::spawn(move || (0..N).for_each(|i| tx.send(i).unwrap()))
+            }).collect();
+            let cons : Vec<_> = (0..2).map(|_| {
+                let rx = rx.clone();
+                thread::spawn(move || (0..N).map(|_| rx.recv().unwrap()).sum::<u64>())
+            }).collect();
+            prods.into_iter().for_each(|h| h.join().unwrap());
+            let sum : u64 = cons.into_iter().map(|h| h.join().unwrap()).sum();
+            assert_eq!(sum, 2*(0..N).sum::<u64>());
+        }};
+    }
+
+    #[test]
+    fn test_wait_strategies() {
+        wait_test!(Yield);
+        wait_test!(Backoff::default());
+        wait_test!(Park::new());
+    }
+
+    #[test]
+    fn test_park_disconnect() {
+        let (tx, rx) = spsc_with::<u8, _>(2, Park::new());
+        let handle = thread::spawn(move || rx.recv());
+        thread::sleep(std::time::Duration::from_millis(10));
+        drop(tx);
+        assert_eq!(handle.join().unwrap(), Err(RecvError));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let timeout = Duration::from_millis(10);
+
+        let (tx, rx) = spsc_with::<u8, _>(2, Park::new());
+        assert_eq!(rx.recv_timeout(timeout), Err(RecvTimeoutError::Timeout));
+
+        (0..4).for_each(|i| tx.send(i).unwrap());
+        assert_eq!(tx.send_timeout(4, timeout), Err(SendTimeoutError::Timeout(4)));
+        assert_eq!(rx.recv_deadline(Instant::now() + timeout), Ok(0));
+        assert_eq!(tx.send_deadline(4, Instant::now() + timeout), Ok(()));
+
+        drop(rx);
+        assert_eq!(tx.send_timeout(5, timeout), Err(SendTimeoutError::Disconnected(5)));
+
+        let (tx, rx) = mpmc_with::<u8, _>(2, Yield);
+        let handle = thread::spawn(move || {
+            thread::sleep(timeout);
+            tx.send(1).unwrap();
+        });
+        assert_eq!(rx.recv_timeout(Duration::from_secs(10)), Ok(1));
+        handle.join().unwrap();
+        assert_eq!(rx.recv_timeout(timeout), Err(RecvTimeoutError::Disconnected));
+    }
+
+    #[test]
+    fn test_len() {
+        let (tx, rx) = spsc::<u8>(2);
+        assert_eq!(tx.capacity(), 4);
+        assert!(tx.is_empty() && rx.is_empty());
+
+        (0..3).for_each(|i| tx.send(i).unwrap());
+        assert_eq!(rx.len(), 3);
+        assert_eq!(tx.remaining(), 1);
+
+        tx.send(3).unwrap();
+        assert!(tx.is_full() && rx.is_full());
+
+        rx.recv().unwrap();
+        assert_eq!(tx.len(), 3);
+    }
+
+    #[test]
+    fn test_send_recv_many() {
+        const N : u64 = 1_000;
+
+        let (tx, rx) = mpsc_with::<u64, _>(4, Yield);
+        let handles : Vec<_> = (0..2).map(|_| {
+            let tx = tx.clone();
+            thread::spawn(move || tx.send_many((0..N).collect::<Vec<_>>()).unwrap())
+        }).collect();
+        drop(tx);
+
+        let mut out = Vec::new();
+        while rx.recv_many(&mut out, 8).is_ok() {
+            assert!(out.len() <= 2 * N as usize);
+        }
+        handles.into_iter().for_each(|h| h.join().unwrap());
+
+        assert_eq!(out.len(), 2 * N as usize);
+        assert_eq!(out.iter().sum::<u64>(), 2 * (0..N).sum::<u64>());
+
+        let (tx, rx) = spsc::<u64>(2);
+        tx.send_many(vec![1, 2]).unwrap();
+        assert_eq!(rx.recv_many(&mut out, 0), Ok(0));
+        drop(rx);
+        let rest = tx.send_many(vec![3, 4, 5, 6]).unwrap_err().0;
+        assert_eq!(rest, vec![3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_send_many_iterators() {
+        let (tx, rx) = spsc::<u64>(2);
+
+        // no lower bound on the length, every item must still be sent
+        tx.send_many((0..3).filter(|_| true)).unwrap();
+        assert_eq!(rx.len(), 3);
+
+        // a panic while pulling items unwinds without claiming a slot
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            tx.send_many((3..6).map(|i| if i == 4 { panic!("iterator") } else { i }))
+        }));
+        assert!(result.is_err());
+        assert_eq!(rx.len(), 3);
+
+        tx.send(3).unwrap();
+        let mut out = Vec::new();
+        assert_eq!(rx.recv_many(&mut out, 8), Ok(4));
+        assert_eq!(out, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_reserve_read_guard() {
+        const N : u64 = 1_000;
+
+        let (mut tx, mut rx) = spsc_with::<[u64; 8], _>(2, Park::new());
+        let handle = thread::spawn(move || {
+            for i in 0..N {
+                tx.reserve().unwrap().write([i; 8]);
+            }
+        });
+
+        let mut sum = 0;
+        for i in 0..N {
+            let guard = rx.read_guard().unwrap();
+            assert_eq!(guard[7], i);
+            sum += if i % 2 == 0 { guard[0] } else { guard.take()[0] };
+        }
+        assert_eq!(sum, (0..N).sum());
+        assert!(rx.read_guard().is_err());
+        handle.join().unwrap();
+
+        let (mut tx, mut rx) = spsc::<u8>(2);
+        assert_eq!(rx.try_read_guard().err(), Some(TryRecvError::Empty));
+        tx.try_reserve().unwrap().commit();
+        assert!(tx.is_empty());
+        (0..4).for_each(|i| tx.send(i).unwrap());
+        assert_eq!(tx.try_reserve().err(), Some(TrySendError::Full(())));
+        drop(rx);
+        assert_eq!(tx.try_reserve().err(), Some(TrySendError::Disconnected(())));
+    }
+
+    #[test]
+    fn test_force_send() {
+        let (tx, rx) = overwriting::<u8, Single>(2);
+
+        for i in 0..4 {
+            assert_eq!(tx.force_send(i), Ok(None));
+        }
+        assert_eq!(tx.force_send(4), Ok(Some(0)));
+        assert_eq!(rx.overwritten(), 1);
+        assert!(rx.is_full());
+
+        assert_eq!(rx.recv(), Ok(1));
+        drop(rx);
+        assert_eq!(tx.force_send(5), Err(SendError(5)));
+    }
+
+    #[test]
+    fn test_overwriting_handles() {
+        let (mut tx, rx) = overwriting_with::<u8, Single, _>(2, Park::new());
+        tx.reserve().unwrap().write(0);
+        assert_eq!(tx.force_send(1), Ok(None));
+        assert!(format!("{:?}", tx).starts_with("OverwritingSender { ring: Ring {"));
+
+        let (tx, rx1) = overwriting::<u8, Multi>(2);
+        let tx1 = tx.clone();
+        let handle = thread::spawn(move || tx1.force_send(2));
+        assert_eq!(handle.join().unwrap(), Ok(None));
+        assert_eq!(rx1.clone().recv(), Ok(2));
+        drop(rx);
+    }
+
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // the smallest executor there is: poll, park until woken, poll again
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_select_deregisters() {
+        let (tx0, rx0) = spsc::<u8>(2);
+        let (_tx1, rx1) = spsc::<u8>(2);
+
+        let mut sel = Select::new();
+        let r0 = sel.recv(&rx0);
+        sel.recv(&rx1);
+        for _ in 0..3 {
+            assert_eq!(sel.select_timeout(Duration::from_millis(1)), Err(SelectTimeoutError));
+        }
+        assert_eq!(rx0.shared.recv_wakers.len(), 0);
+        assert_eq!(rx1.shared.recv_wakers.len(), 0);
+
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            tx0.send(0).unwrap();
+        });
+        assert_eq!(sel.select(), r0);
+        handle.join().unwrap();
+
+        // the channel that did not wake the select is left clean too
+        assert_eq!(rx1.shared.recv_wakers.len(), 0);
+    }
+
+    #[test]
+    fn test_select_stalled_sender() {
+        let (_tx, rx) = stamped::<u8, Multi, Multi>(2);
+        rx.shared.ring.stall_enqueue();
+        assert_eq!(rx.len(), 1);
+
+        // the slot is claimed but holds nothing yet, so nothing is ready
+        assert!(!rx.is_ready());
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        let mut sel = Select::new();
+        sel.recv(&rx);
+        assert_eq!(sel.try_select(), Err(TrySelectError));
+    }
+
+    #[test]
+    fn test_poll_recv() {
+        let (tx, rx) = spsc::<u8>(2);
+        let counter = Arc::new(CountingWaker::default());
+        let waker = Waker::from(Arc::clone(&counter));
+        let mut cx = Context::from_waker(&waker);
+
+        assert_eq!(rx.poll_recv(&mut cx), Poll::Pending);
+        assert_eq!(rx.poll_recv(&mut cx), Poll::Pending);
+        tx.send(1).unwrap();
+        // registered once however often it was polled
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert_eq!(rx.poll_recv(&mut cx), Poll::Ready(Some(1)));
+
+        assert_eq!(rx.poll_recv(&mut cx), Poll::Pending);
+        drop(tx);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 2);
+        assert_eq!(rx.poll_recv(&mut cx), Poll::Ready(None));
+    }
+
+    #[test]
+    fn test_send_async_full() {
+        let (mut tx, rx) = spsc::<u8>(2);
+        let counter = Arc::new(CountingWaker::default());
+        let waker = Waker::from(Arc::clone(&counter));
+        let mut cx = Context::from_waker(&waker);
+
+        (0..4).for_each(|i| tx.send(i).unwrap());
+        let mut future = tx.send_async(4);
+        assert_eq!(Pin::new(&mut future).poll(&mut cx), Poll::Pending);
+
+        assert_eq!(rx.recv(), Ok(0));
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert_eq!(Pin::new(&mut future).poll(&mut cx), Poll::Ready(Ok(())));
+        drop(future);
+
+        let mut future = tx.send_async(5);
+        assert_eq!(Pin::new(&mut future).poll(&mut cx), Poll::Pending);
+        drop(rx);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 2);
+        assert_eq!(Pin::new(&mut future).poll(&mut cx), Poll::Ready(Err(SendError(5))));
+    }
+
+    #[test]
+    fn test_cancelled_futures() {
+        let (mut tx, mut rx) = spsc::<u8>(2);
+        let waker = Waker::from(Arc::new(CountingWaker::default()));
+        let mut cx = Context::from_waker(&waker);
+        let shared = Arc::clone(&tx.shared);
+
+        let mut future = rx.recv_async();
+        assert_eq!(Pin::new(&mut future).poll(&mut cx), Poll::Pending);
+        assert_eq!(Pin::new(&mut future).poll(&mut cx), Poll::Pending);
+        assert_eq!(shared.recv_wakers.len(), 1);
+        drop(future);
+        assert_eq!(shared.recv_wakers.len(), 0);
+
+        (0..4).for_each(|i| tx.send(i).unwrap());
+        let mut future = tx.send_async(4);
+        assert_eq!(Pin::new(&mut future).poll(&mut cx), Poll::Pending);
+        assert_eq!(shared.send_wakers.len(), 1);
+        drop(future);
+        assert_eq!(shared.send_wakers.len(), 0);
+    }
+
+    #[test]
+    fn test_async_bridge() {
+        const N : u64 = 10_000;
+
+        // blocking producers feeding an async consumer
+        let (tx, mut rx) = mpsc_with::<u64, _>(2, Park::new());
+        let handles = (0..2)
+            .map(|_| {
+                let tx = tx.clone();
+                thread::spawn(move || (0..N).for_each(|i| tx.send(i).unwrap()))
+            })
+            .collect::<Vec<_>>();
+        drop(tx);
+
+        let sum = block_on(async {
+            let mut sum = 0;
+            while let Ok(value) = rx.recv_async().await {
+                sum += value;
+            }
+            sum
+        });
+        handles.into_iter().for_each(|handle| handle.join().unwrap());
+        assert_eq!(sum, N * (N - 1));
+
+        // an async producer feeding a blocking consumer
+        let (mut tx, rx) = spsc_with::<u64, _>(2, Park::new());
+        let handle = thread::spawn(move || {
+            let mut sum = 0;
+            while let Ok(value) = rx.recv() {
+                sum += value;
+            }
+            sum
+        });
+        block_on(async {
+            for i in 0..N {
+                tx.send_async(i).await.unwrap();
+            }
+        });
+        drop(tx);
+        assert_eq!(handle.join().unwrap(), N * (N - 1) / 2);
+    }
+}
@@ -1,5 +1,36 @@
-use std::sync::atomic::{AtomicU32, Ordering};
-
+use core::sync::atomic::Ordering;
+
+use crate::sync::AtomicU32;
+
+// A ring has two cursors, one per side. `head` is the position up to which
+// slots are claimed by that side, `tail` the position up to which they are
+// done with and handed to the other side: the producer tail publishes
+// written slots to consumers, the consumer tail gives read slots back to
+// producers. Slot contents are only ever synchronised through the tails:
+//
+// - a tail moves with a release, `exchange_back` or a plain store on the
+//   single threaded paths, after the slots behind it were written or read;
+// - the other side loads it with an acquire, `back` or `reached`, before
+//   it touches those slots, so a read sees the write it follows and a
+//   write never lands on a slot still being read.
+//
+// Concurrent committers wait on `exchange_back` for their turn, which makes
+// a tail move as a chain of read-modify-writes: one acquire of the latest
+// value synchronises with every committer before it through the release
+// sequence, the waiting itself needs no ordering.
+//
+// Heads only arbitrate which thread owns a slot, but they also carry what
+// the claimer saw of the other side: a claim is checked against the tail
+// it loaded after its head, and `exchange_front` releases that head so that
+// a thread acquiring it with `front` loads the same tail or a later one.
+// So a consumer head is never past the producer tail loaded after it, a
+// producer head never more than `size` past the consumer tail loaded after
+// it, and an empty or full answer was true when the head was loaded.
+//
+// Nothing here relies on a single total order of the cursor operations, so
+// no operation is `SeqCst`. Handshakes that store to a flag of their own
+// and then check the ring, like the async wakers of a channel, bring their
+// own fences.
 #[repr(align(64))]
 #[repr(C)]
 #[derive(Debug)]
@@ -11,52 +42,62 @@ pub(crate) struct Cursor {
 }
 
 impl Cursor {
-    #[inline(always)]
-    pub fn new(size: u32) -> Self {
-        Cursor{ 
-            head: AtomicU32::new(0), 
-            tail: AtomicU32::new(0), 
-            size: size,
-            mask: size-1 
+    const_fn! {
+        #[inline(always)]
+        pub fn new(size: u32) -> Self {
+            Cursor{ 
+                head: AtomicU32::new(0), 
+                tail: AtomicU32::new(0), 
+                size,
+                mask: size-1 
+            }
         }
     }
 
+    // positions grow monotonically and wrap around at u32::MAX, they are
+    // only masked down to a slot index when the buffer is accessed
     #[inline]
-    pub fn next(&mut self, head: u32) -> u32 {
-        head.wrapping_add(1) & self.mask
+    pub fn next(&self, head: u32) -> u32 {
+        head.wrapping_add(1)
     }
 
     #[inline]
-    pub fn front(&mut self) -> u32 {
-        self.head.load(Ordering::SeqCst)
+    pub fn index(&self, pos: u32) -> usize {
+        (pos & self.mask) as usize
     }
+
     #[inline]
-    pub fn back(&mut self) -> u32 {
-        self.tail.load(Ordering::SeqCst)
+    pub fn size(&self) -> u32 {
+        self.size
     }
 
+    // acquire: the other cursor must be loaded after this one
     #[inline]
-    pub fn reached(&mut self, head: u32) -> bool {
-        assert!(head < self.size);
-        
-        self.tail.load(Ordering::SeqCst) == head
+    pub fn front(&self) -> u32 {
+        self.head.load(Ordering::Acquire)
+    }
+    #[inline]
+    pub fn back(&self) -> u32 {
+        self.tail.load(Ordering::Acquire)
     }
 
     #[inline]
-    pub fn exchange_front(&mut self, head: u32, next: u32) -> bool {
-        assert!(head < self.size && next < self.size);
+    pub fn reached(&self, head: u32) -> bool {
+        self.tail.load(Ordering::Acquire) == head
+    }
 
+    // a failed exchange is retried after loading again, it orders nothing
+    #[inline]
+    pub fn exchange_front(&self, head: u32, next: u32) -> bool {
         self.head
-        .compare_exchange(head, next, Ordering::SeqCst, Ordering::SeqCst)
+        .compare_exchange(head, next, Ordering::Release, Ordering::Relaxed)
         .is_ok()
     }
 
     #[inline]
-    pub fn exchange_back(&mut self, tail: u32, next: u32) -> bool {
-        assert!(tail < self.size && next < self.size);
-
+    pub fn exchange_back(&self, tail: u32, next: u32) -> bool {
         self.tail
-        .compare_exchange(tail, next, Ordering::SeqCst, Ordering::SeqCst)
+        .compare_exchange(tail, next, Ordering::Release, Ordering::Relaxed)
         .is_ok()
     }
 }
@@ -79,7 +120,7 @@ mod tests {
     #[test]
     fn test_next() {
         const SZ : u32 = 8;
-        let mut cursor = Cursor::new(SZ);
+        let cursor = Cursor::new(SZ);
         let h = cursor.front();
         let n = cursor.next(h);
 
@@ -90,7 +131,7 @@ mod tests {
     #[test]
     fn test_exchange() {
         const SZ : u32 = 8;
-        let mut cursor = Cursor::new(SZ);
+        let cursor = Cursor::new(SZ);
         let h = cursor.front();
         let n = cursor.next(h);
 
@@ -109,13 +150,24 @@ mod tests {
     #[test]
     fn test_overflow() {
         const SZ : u32 = 8;
-        let mut cursor = Cursor::new(SZ);
+        let cursor = Cursor::new(SZ);
         cursor.exchange_front(0, 7);
         
         let h = cursor.front();
         let n = cursor.next(h);
         
         assert_eq!(h, 7);
+        assert_eq!(n, 8);
+        assert_eq!(cursor.index(h), 7);
+        assert_eq!(cursor.index(n), 0);
+
+        cursor.exchange_front(7, u32::MAX);
+
+        let h = cursor.front();
+        let n = cursor.next(h);
+
+        assert_eq!(cursor.index(h), 7);
         assert_eq!(n, 0);
+        assert_eq!(cursor.index(n), 0);
     }
 }
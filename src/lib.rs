@@ -1,7 +1,29 @@
+#![cfg_attr(not(any(feature = "std", test, loom)), no_std)]
+
+extern crate alloc;
+
+#[macro_use]
+mod sync;
 mod cursor;
 mod buffer;
+#[cfg(feature = "std")]
+mod error;
+#[cfg(feature = "std")]
+mod wait;
+#[cfg(feature = "std")]
+mod select;
+#[cfg(feature = "std")]
+mod unbounded;
+#[cfg(test)]
+mod model;
+#[cfg(test)]
+mod test_util;
 pub mod ring;
+pub mod stamped;
+pub mod broadcast;
+pub mod sequencer;
 
+#[cfg(feature = "std")]
 pub mod channel;
 
 #[cfg(test)]
@@ -4,7 +4,10 @@ use std::thread;
 use std::sync::mpsc::channel;
 use std::time::{Instant};
 
-use ring::channel::channel as ring_channel;
+use ring::channel::{self, spsc, mpsc, mpmc, Backend, BusySpin, Multi, Receiver, Sender, Single};
+
+// the handles of a channel, on whichever ring backs it
+type Pair<P, C, B> = (Sender<u64, P, BusySpin, B>, Receiver<u64, C, BusySpin, B>);
 
 fn main(){
     let max = 1_000_000;
@@ -14,16 +17,32 @@ fn main(){
     eprintln!("chan {:?}", Instant::now() - t);
     
     let t = Instant::now();
-    ring1(max);
+    ring1(spsc(8), max);
     eprintln!("ring {:?}", Instant::now() - t);
 
+    let t = Instant::now();
+    ring1(channel::stamped(8), max);
+    eprintln!("stamped {:?}", Instant::now() - t);
+
     let t = Instant::now();
     chan2(max);
     eprintln!("chan {:?}", Instant::now() - t);
     
     let t = Instant::now();
-    ring2(max);
+    ring2(mpsc(8), max);
     eprintln!("ring {:?}", Instant::now() - t);
+
+    let t = Instant::now();
+    ring2(channel::stamped(8), max);
+    eprintln!("stamped {:?}", Instant::now() - t);
+
+    let t = Instant::now();
+    ring4(mpmc(8), max);
+    eprintln!("ring {:?}", Instant::now() - t);
+
+    let t = Instant::now();
+    ring4(channel::stamped(8), max);
+    eprintln!("stamped {:?}", Instant::now() - t);
 }
 
 #[allow(dead_code)]
@@ -43,7 +62,7 @@ fn chan1(max: usize){
 
         match rx.recv().ok() {
             Some(v) => {
-                sum += v as u64;
+                sum += v;
                 break 'inner;
             },
             None => continue,
@@ -81,7 +100,7 @@ fn chan2(max: usize){
         
         match rx.recv().ok() {
             Some(v) => {
-                sum += v as u64;
+                sum += v;
                 break 'inner;
             },
             None => continue,
@@ -96,46 +115,84 @@ fn chan2(max: usize){
 
 
 #[allow(dead_code)]
-fn ring1 (max: usize) {
-    let (tx, rx) = ring_channel::<u64>(8);
+fn ring1<B>((tx, rx): Pair<Single, Single, B>, max: usize)
+where
+    B: Backend<u64> + Send + Sync + 'static,
+{
 
     let handle = thread::spawn(move ||{
         for i in 0..max*2 {
-            tx.send(i as u64);
+            tx.send(i as u64).unwrap();
         }
     });
 
     let mut sum = 0u64;    
     for _ in 0..max*2 {
-        sum += rx.recv();
+        sum += rx.recv().unwrap();
     }
     eprintln!("ring(1) sum = {}", sum);
     handle.join().unwrap();
 }
 
 #[allow(dead_code)]
-fn ring2 (max: usize) {
-    let (tx0, rx) = ring_channel::<u64>(8);
-    let tx1 = rx.clone();
+fn ring2<B>((tx0, rx): Pair<Multi, Single, B>, max: usize)
+where
+    B: Backend<u64> + Send + Sync + 'static,
+{
+    let tx1 = tx0.clone();
 
     let handle0 = thread::spawn(move ||{
         for i in 0..max {
-            tx0.send(i as u64);
+            tx0.send(i as u64).unwrap();
         }
     });
 
     let handle1 = thread::spawn(move ||{
         for i in 0..max {
-            tx1.send(i as u64);
+            tx1.send(i as u64).unwrap();
         }
     });
 
     let mut sum = 0u64;    
     for _ in 0..max*2 {
-        sum += rx.recv();
+        sum += rx.recv().unwrap();
     }
     
     eprintln!("ring(2) sum = {}", sum);
     handle0.join().unwrap();
     handle1.join().unwrap();
 }
+
+// four producers and four consumers contending on both sides
+#[allow(dead_code)]
+fn ring4<B>((tx, rx): Pair<Multi, Multi, B>, max: usize)
+where
+    B: Backend<u64> + Send + Sync + 'static,
+{
+    let prods : Vec<_> = (0..4).map(|_| {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            for i in 0..max / 2 {
+                tx.send(i as u64).unwrap();
+            }
+        })
+    }).collect();
+    drop(tx);
+
+    let cons : Vec<_> = (0..4).map(|_| {
+        let rx = rx.clone();
+        thread::spawn(move || {
+            let mut sum = 0u64;
+            for _ in 0..max / 2 {
+                sum += rx.recv().unwrap();
+            }
+            sum
+        })
+    }).collect();
+
+    for prod in prods {
+        prod.join().unwrap();
+    }
+    let sum : u64 = cons.into_iter().map(|con| con.join().unwrap()).sum();
+    eprintln!("ring(4) sum = {}", sum);
+}
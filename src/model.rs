@@ -0,0 +1,587 @@
+//! Deterministic interleaving checker for the cursor protocol.
+//!
+//! `check` runs a set of closures as threads, but only ever lets one of them
+//! run: every cursor atomic is a switch point where a scheduler picks which
+//! thread goes next. Runs are repeated, each time taking a different choice
+//! at the deepest switch point with one left, until every schedule has been
+//! explored. To keep that finite the number of preemptions, switching away
+//! from a thread that could have gone on, is bounded; switching away from a
+//! thread that spins is free, it cannot make progress on its own anyway.
+//!
+//! Schedules are sequentially consistent interleavings, reorderings allowed
+//! by weaker memory orderings are not modelled. Built with `--cfg loom`,
+//! `check` hands the same scenarios to loom instead, which also explores
+//! the values a load may see under the orderings it was given and catches
+//! slot accesses that the cursors fail to order. That takes minutes:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --release --lib model
+//! ```
+
+// the checker below is left unused when loom takes over
+#![cfg_attr(loom, allow(dead_code, unused_imports))]
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::sync::atomic::{self, Ordering};
+use std::thread;
+
+// a run longer than this is taken for a livelock
+const MAX_STEPS: usize = 20_000;
+
+const NONE: usize = usize::MAX;
+
+struct Branch {
+    choices: Vec<usize>,
+    index: usize,
+}
+
+struct State {
+    active: usize,
+    finished: Vec<bool>,
+    // spun on state that did not change since it was read, not worth
+    // running again until something is stored
+    yielded: Vec<bool>,
+    // stores so far, and per thread the count at its first operation since
+    // it last spun
+    version: usize,
+    since: Vec<Option<usize>>,
+    preemptions: usize,
+    bound: usize,
+    path: Vec<Branch>,
+    depth: usize,
+    steps: usize,
+    aborted: bool,
+}
+
+struct Execution {
+    state: Mutex<State>,
+    wakeup: Condvar,
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+// checks in progress, the instrumented operations stay out of the way of
+// everything else while there are none
+static CHECKS: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+
+struct Checking;
+
+impl Drop for Checking {
+    fn drop(&mut self) {
+        CHECKS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<(Arc<Execution>, usize)>> = const { RefCell::new(None) };
+}
+
+impl State {
+    // the thread to run once `me` reached a switch point, replaying the
+    // recorded path first and recording new branches past its end
+    fn schedule(&mut self, me: usize, spinning: bool) -> usize {
+        if me != NONE {
+            let since = self.since[me].take();
+            if spinning {
+                self.yielded[me] = since == Some(self.version);
+            } else {
+                self.since[me] = since.or(Some(self.version));
+            }
+        }
+        let runnable = (0..self.finished.len())
+            .filter(|&thread| !self.finished[thread])
+            .collect::<Vec<_>>();
+        if runnable.is_empty() {
+            return NONE;
+        }
+        assert!(
+            runnable.iter().any(|&thread| !self.yielded[thread]),
+            "every thread spins waiting for another one"
+        );
+
+        let running = me != NONE && !self.finished[me];
+        let eligible = runnable.into_iter().filter(|&thread| !self.yielded[thread]);
+        let choices = if running && !spinning {
+            // going on is free, switching away costs a preemption
+            let mut choices = vec![me];
+            if self.preemptions < self.bound {
+                choices.extend(eligible.filter(|&thread| thread != me));
+            }
+            choices
+        } else {
+            eligible.collect::<Vec<_>>()
+        };
+
+        if self.depth == self.path.len() {
+            self.path.push(Branch { choices, index: 0 });
+        } else {
+            assert_eq!(self.path[self.depth].choices, choices, "model run is not deterministic");
+        }
+        let branch = &self.path[self.depth];
+        let next = branch.choices[branch.index];
+        self.depth += 1;
+
+        if running && !spinning && next != me {
+            self.preemptions += 1;
+        }
+        next
+    }
+
+    // moves to the next unexplored schedule, false once all were explored
+    fn advance(&mut self) -> bool {
+        while let Some(branch) = self.path.last_mut() {
+            if branch.index + 1 < branch.choices.len() {
+                branch.index += 1;
+                return true;
+            }
+            self.path.pop();
+        }
+        false
+    }
+}
+
+impl Execution {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn wait_turn(&self, mut state: MutexGuard<'_, State>, me: usize) {
+        while state.active != me && !state.aborted {
+            state = self.wakeup.wait(state).unwrap_or_else(|err| err.into_inner());
+        }
+        if state.aborted {
+            drop(state);
+            panic!("model run aborted");
+        }
+    }
+
+    fn switch(&self, me: usize, spinning: bool) {
+        let mut state = self.lock();
+        if state.aborted {
+            drop(state);
+            panic!("model run aborted");
+        }
+        state.steps += 1;
+        if state.steps > MAX_STEPS {
+            state.aborted = true;
+            drop(state);
+            self.wakeup.notify_all();
+            panic!("model run did not finish within {} steps", MAX_STEPS);
+        }
+
+        state.active = state.schedule(me, spinning);
+        self.wakeup.notify_all();
+        self.wait_turn(state, me);
+    }
+
+    fn progress(&self) {
+        let mut state = self.lock();
+        state.version += 1;
+        state.yielded.iter_mut().for_each(|yielded| *yielded = false);
+    }
+
+    fn finish(&self, me: usize, panic: Option<Box<dyn Any + Send>>) {
+        let mut state = self.lock();
+        state.finished[me] = true;
+        state.yielded.iter_mut().for_each(|yielded| *yielded = false);
+        match panic {
+            Some(payload) => {
+                state.aborted = true;
+                self.panic.lock().unwrap().get_or_insert(payload);
+            },
+            None if !state.aborted => state.active = state.schedule(me, false),
+            None => (),
+        }
+        drop(state);
+        self.wakeup.notify_all();
+    }
+}
+
+// called by the instrumented operations, a no-op outside of a model run or
+// while a thread unwinds from an aborted one
+#[inline]
+fn switch(spinning: bool) {
+    if CHECKS.load(Ordering::Relaxed) == 0 || thread::panicking() {
+        return;
+    }
+    let current = CURRENT.with(|current| current.borrow().clone());
+    if let Some((execution, me)) = current {
+        execution.switch(me, spinning);
+    }
+}
+
+// called after an operation that changed shared state
+#[inline]
+fn progress() {
+    if CHECKS.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let current = CURRENT.with(|current| current.borrow().clone());
+    if let Some((execution, _)) = current {
+        execution.progress();
+    }
+}
+
+/// Gives the other threads of a model run a turn; a plain spin hint
+/// otherwise.
+#[inline]
+pub(crate) fn spin_loop() {
+    switch(true);
+    core::hint::spin_loop();
+}
+
+/// `AtomicU32` whose every operation is a switch point.
+pub(crate) struct AtomicU32(atomic::AtomicU32);
+
+impl AtomicU32 {
+    #[inline]
+    pub const fn new(value: u32) -> Self {
+        AtomicU32(atomic::AtomicU32::new(value))
+    }
+
+    #[inline]
+    pub fn load(&self, order: Ordering) -> u32 {
+        switch(false);
+        self.0.load(order)
+    }
+
+    #[inline]
+    pub fn store(&self, value: u32, order: Ordering) {
+        switch(false);
+        self.0.store(value, order);
+        progress();
+    }
+
+    #[inline]
+    pub fn compare_exchange(&self, current: u32, new: u32, success: Ordering, failure: Ordering)
+        -> Result<u32, u32> {
+        switch(false);
+        let result = self.0.compare_exchange(current, new, success, failure);
+        if result.is_ok() {
+            progress();
+        }
+        result
+    }
+}
+
+impl fmt::Debug for AtomicU32 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+pub(crate) type Body = Box<dyn FnOnce() + Send>;
+
+/// Explores every schedule of the threads built by `setup` with at most
+/// `bound` preemptions, running `verify` after each one. Returns how many
+/// schedules were explored.
+#[cfg(not(loom))]
+pub(crate) fn check<S, V>(bound: usize, setup: S) -> usize
+where
+    S: Fn() -> (Vec<Body>, V),
+    V: FnOnce(),
+{
+    CHECKS.fetch_add(1, Ordering::Relaxed);
+    let _checking = Checking;
+
+    let mut path = Vec::new();
+    let mut runs = 0;
+    loop {
+        let (bodies, verify) = setup();
+        let count = bodies.len();
+
+        let execution = Arc::new(Execution {
+            state: Mutex::new(State {
+                active: NONE,
+                finished: vec![false; count],
+                yielded: vec![false; count],
+                version: 0,
+                since: vec![None; count],
+                preemptions: 0,
+                bound,
+                path,
+                depth: 0,
+                steps: 0,
+                aborted: false,
+            }),
+            wakeup: Condvar::new(),
+            panic: Mutex::new(None),
+        });
+
+        let handles = bodies.into_iter()
+            .enumerate()
+            .map(|(me, body)| {
+                let execution = Arc::clone(&execution);
+                thread::spawn(move || {
+                    CURRENT.with(|current| *current.borrow_mut() = Some((Arc::clone(&execution), me)));
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                        execution.wait_turn(execution.lock(), me);
+                        body();
+                    }));
+                    CURRENT.with(|current| current.borrow_mut().take());
+                    execution.finish(me, result.err());
+                })
+            })
+            .collect::<Vec<_>>();
+
+        {
+            let mut state = execution.lock();
+            state.active = state.schedule(NONE, false);
+        }
+        execution.wakeup.notify_all();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        runs += 1;
+
+        if let Some(payload) = execution.panic.lock().unwrap().take() {
+            eprintln!("model run {} failed", runs);
+            panic::resume_unwind(payload);
+        }
+        verify();
+
+        let execution = Arc::try_unwrap(execution).ok().unwrap();
+        let mut state = execution.state.into_inner().unwrap();
+        if !state.advance() {
+            return runs;
+        }
+        path = state.path;
+    }
+}
+
+/// Explores the executions of the threads built by `setup` with loom, at
+/// most `bound` preemptions unless `LOOM_MAX_PREEMPTIONS` says otherwise,
+/// running `verify` after each one. Returns how many were explored.
+#[cfg(loom)]
+pub(crate) fn check<S, V>(bound: usize, setup: S) -> usize
+where
+    S: Fn() -> (Vec<Body>, V) + Send + Sync + 'static,
+    V: FnOnce(),
+{
+    let runs = Arc::new(atomic::AtomicUsize::new(0));
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound.get_or_insert(bound);
+    builder.check({
+        let runs = Arc::clone(&runs);
+        move || {
+            let (bodies, verify) = setup();
+            let handles = bodies.into_iter()
+                .map(loom::thread::spawn)
+                .collect::<Vec<_>>();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            verify();
+            runs.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    runs.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(not(loom))]
+    use std::sync::Arc;
+    // the last handle drops the ring, loom has to see that synchronise
+    #[cfg(loom)]
+    use loom::sync::Arc;
+    use std::sync::Mutex;
+    use crate::ring::Ring;
+    use crate::stamped::StampedRing;
+    use super::*;
+
+    type Item = (usize, usize);
+
+    // the operations under test, one implementation per ring and path
+    trait Queue: Send + Sync + 'static {
+        fn enqueue(&self, value: Item) -> Option<Item>;
+        fn dequeue(&self) -> Option<Item>;
+        fn is_empty(&self) -> bool;
+    }
+
+    // the single producer and consumer paths, only sound for one of each
+    struct Single(Ring<Item>);
+
+    impl Queue for Single {
+        fn enqueue(&self, value: Item) -> Option<Item> {
+            unsafe { self.0.single_enqueue(value) }
+        }
+
+        fn dequeue(&self) -> Option<Item> {
+            unsafe { self.0.single_dequeue() }
+        }
+
+        fn is_empty(&self) -> bool {
+            self.0.is_empty()
+        }
+    }
+
+    struct StampedSingle(StampedRing<Item>);
+
+    impl Queue for StampedSingle {
+        fn enqueue(&self, value: Item) -> Option<Item> {
+            unsafe { self.0.single_enqueue(value) }
+        }
+
+        fn dequeue(&self) -> Option<Item> {
+            unsafe { self.0.single_dequeue() }
+        }
+
+        fn is_empty(&self) -> bool {
+            self.0.is_empty()
+        }
+    }
+
+    impl Queue for Ring<Item> {
+        fn enqueue(&self, value: Item) -> Option<Item> {
+            self.multi_enqueue(value)
+        }
+
+        fn dequeue(&self) -> Option<Item> {
+            self.multi_dequeue()
+        }
+
+        fn is_empty(&self) -> bool {
+            Ring::is_empty(self)
+        }
+    }
+
+    impl Queue for StampedRing<Item> {
+        fn enqueue(&self, value: Item) -> Option<Item> {
+            self.multi_enqueue(value)
+        }
+
+        fn dequeue(&self) -> Option<Item> {
+            self.multi_dequeue()
+        }
+
+        fn is_empty(&self) -> bool {
+            StampedRing::is_empty(self)
+        }
+    }
+
+    // every producer pushes its own tagged sequence and consumers pop as
+    // many items as they would get in turns, all of them skipping an item
+    // on a full or empty ring instead of waiting: a wait for another thread
+    // that has nothing left to do would never end. Afterwards the rest is
+    // drained, every pushed item must come out exactly once and, per
+    // consumer, in the order its producer pushed it
+    fn mpmc<Q: Queue>(new: fn() -> Q, producers: usize, consumers: usize, items: usize, bound: usize) -> usize {
+        check(bound, move || {
+            let ring = Arc::new(new());
+            let pushed = Arc::new(Mutex::new(Vec::new()));
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let mut bodies : Vec<Body> = Vec::new();
+
+            for producer in 0..producers {
+                let ring = Arc::clone(&ring);
+                let pushed = Arc::clone(&pushed);
+                bodies.push(Box::new(move || {
+                    for item in 0..items {
+                        if ring.enqueue((producer, item)).is_none() {
+                            pushed.lock().unwrap().push((producer, item));
+                        }
+                    }
+                }));
+            }
+
+            for _ in 0..consumers {
+                let ring = Arc::clone(&ring);
+                let received = Arc::clone(&received);
+                bodies.push(Box::new(move || {
+                    let seen = (0..(producers * items).div_ceil(consumers))
+                        .filter_map(|_| ring.dequeue())
+                        .collect::<Vec<_>>();
+                    received.lock().unwrap().push(seen);
+                }));
+            }
+
+            let verify = move || {
+                let mut received = received.lock().unwrap();
+                received.push(std::iter::from_fn(|| ring.dequeue()).collect());
+                assert!(ring.is_empty());
+                for seen in received.iter() {
+                    for producer in 0..producers {
+                        let order = seen.iter()
+                            .filter(|value| value.0 == producer)
+                            .map(|value| value.1)
+                            .collect::<Vec<_>>();
+                        assert!(order.windows(2).all(|w| w[0] < w[1]), "out of order: {:?}", seen);
+                    }
+                }
+                let mut all = received.iter().flatten().copied().collect::<Vec<_>>();
+                all.sort_unstable();
+                let mut expected = pushed.lock().unwrap().clone();
+                expected.sort_unstable();
+                assert_eq!(all, expected);
+            };
+
+            (bodies, verify)
+        })
+    }
+
+    // the schedules grow exponentially with threads, items and preemptions,
+    // the sizes below keep each check within a few seconds, up to a couple
+    // of minutes under loom
+
+    #[test]
+    fn test_model_spsc() {
+        // more items than slots, so the producer also finds the ring full
+        assert!(mpmc(|| Single(Ring::new(2)), 1, 1, 5, 4) > 1);
+    }
+
+    #[test]
+    fn test_model_spsc_multi() {
+        assert!(mpmc(|| Ring::new(2), 1, 1, 6, 3) > 1);
+    }
+
+    #[test]
+    fn test_model_mpsc() {
+        assert!(mpmc(|| Ring::new(2), 2, 1, 2, 2) > 1);
+    }
+
+    #[test]
+    fn test_model_spmc() {
+        assert!(mpmc(|| Ring::new(2), 1, 2, 4, 2) > 1);
+    }
+
+    #[test]
+    fn test_model_mpmc() {
+        // both items and preemptions at once take minutes
+        assert!(mpmc(|| Ring::new(2), 2, 2, 2, 1) > 1);
+        assert!(mpmc(|| Ring::new(2), 2, 2, 1, 2) > 1);
+    }
+
+    #[test]
+    fn test_model_stamped_spsc() {
+        assert!(mpmc(|| StampedSingle(StampedRing::new(2)), 1, 1, 5, 4) > 1);
+    }
+
+    #[test]
+    fn test_model_stamped_mpsc() {
+        assert!(mpmc(|| StampedRing::new(2), 2, 1, 3, 2) > 1);
+    }
+
+    #[test]
+    fn test_model_stamped_spmc() {
+        assert!(mpmc(|| StampedRing::new(2), 1, 2, 4, 2) > 1);
+    }
+
+    #[test]
+    fn test_model_stamped_mpmc() {
+        assert!(mpmc(|| StampedRing::new(2), 2, 2, 2, 1) > 1);
+        assert!(mpmc(|| StampedRing::new(2), 2, 2, 1, 2) > 1);
+    }
+
+    #[test]
+    fn test_model_finds_races() {
+        // two producers through the single-producer path lose items
+        let result = panic::catch_unwind(|| mpmc(|| Single(Ring::new(2)), 2, 1, 1, 1));
+        assert!(result.is_err());
+    }
+}
+
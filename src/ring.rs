@@ -1,22 +1,45 @@
-use std::mem;
-use std::sync::atomic::{AtomicUsize, Ordering, spin_loop_hint, compiler_fence};
-use std::fmt::Debug;
+use core::cmp;
+use core::marker::PhantomData;
+use core::mem::{self, MaybeUninit};
+use core::ops::Deref;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::fmt::{self, Debug};
+use alloc::vec::Vec;
 
 use crate::cursor::Cursor;
-use crate::buffer::Buffer;
+use crate::sync::spin_loop;
 
+pub use crate::buffer::{Storage, Buffer, Inline};
+
+/// Lock-free ring of `2^log2` slots.
+///
+/// The ring moves items from the threads that enqueue to the threads that
+/// dequeue, so it is `Send` and `Sync` only for `T: Send`:
+///
+/// ```compile_fail,E0277
+/// use std::rc::Rc;
+/// use std::sync::Arc;
+/// use std::thread;
+/// use ring::ring::Ring;
+///
+/// let ring = Arc::new(Ring::<Rc<u8>>::new(2));
+/// thread::spawn(move || { ring.multi_enqueue(Rc::new(0)); });
+/// ```
 #[repr(C)]
-pub struct Ring<T> {
+pub struct Ring<T, S: Storage<T> = Buffer<T>> {
     prod: Cursor,
     cons: Cursor,
-    inner: Buffer<T>,
-    count: AtomicUsize,
+    inner: S,
+    overwritten: AtomicUsize,
+    _marker: PhantomData<T>,
 }
 
-impl<T: Debug> Ring<T> {
+impl<T> Ring<T> {
     #[inline]
     pub fn new(log2 : usize) -> Self {
         assert!(log2 > 1, "log2 must give greather than 1");
+        assert!(log2 < 32, "log2 must give less than 32");
         assert!(mem::size_of::<T>() > 0, "value size must be greather than zero");
 
         let size : usize = 1 << log2;
@@ -25,30 +48,164 @@ impl<T: Debug> Ring<T> {
             prod: Cursor::new(size as u32),
             cons: Cursor::new(size as u32),
             inner: Buffer::new(size),
-            count: AtomicUsize::new(0),
+            overwritten: AtomicUsize::new(0),
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// A ring with inline storage for `N` items, `N` a power of two.
+///
+/// It needs no allocation and `new` is a `const fn`, so it can live in a
+/// `static`; it derefs to `Ring` for all the queue operations.
+#[repr(transparent)]
+pub struct StaticRing<T, const N: usize>(Ring<T, Inline<T, N>>);
+
+impl<T, const N: usize> StaticRing<T, N> {
+    const_fn! {
+        #[inline]
+        pub fn new() -> Self {
+            assert!(N > 1 && N.is_power_of_two(), "size must be a power of two greather than 1");
+            assert!(N <= 1 << 31, "size must be at most 2^31");
+            assert!(mem::size_of::<T>() > 0, "value size must be greather than zero");
+
+            StaticRing(Ring{
+                prod: Cursor::new(N as u32),
+                cons: Cursor::new(N as u32),
+                inner: Inline::new(),
+                overwritten: AtomicUsize::new(0),
+                _marker: PhantomData,
+            })
+        }
+    }
+}
+
+impl<T, const N: usize> Default for StaticRing<T, N> {
+    #[inline]
+    fn default() -> Self {
+        StaticRing::new()
+    }
+}
+
+impl<T, const N: usize> Debug for StaticRing<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("StaticRing").field(&self.0).finish()
+    }
+}
+
+impl<T, const N: usize> Deref for StaticRing<T, N> {
+    type Target = Ring<T, Inline<T, N>>;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T, S: Storage<T>> Ring<T, S> {
+    /// Number of published items not yet claimed by a consumer.
+    ///
+    /// Under concurrent use this is a snapshot that may be stale by the
+    /// time it returns, but it is always within `0..=capacity()`.
+    #[inline]
+    pub fn len(&self) -> usize {
+        // consumer first: the producer tail loaded afterwards can only be
+        // ahead of it
+        let head = self.cons.front();
+        let tail = self.prod.back();
+        cmp::min(tail.wrapping_sub(head), self.prod.size()) as usize
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    /// Whether an enqueue would find a free slot. Unlike `!is_full()` a
+    /// slot only counts once its consumer released it, not when claimed.
+    #[inline]
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn has_free_slot(&self) -> bool {
+        // producer first: the consumer tail loaded afterwards can only be
+        // ahead of it
+        let head = self.prod.front();
+        let tail = self.cons.back();
+        head.wrapping_sub(tail) < self.prod.size()
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.prod.size() as usize
+    }
+
+    /// Number of free slots, `capacity() - len()`.
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        self.capacity() - self.len()
+    }
+
+    /// Number of items evicted by `force_enqueue` so far.
+    #[inline]
+    pub fn overwritten(&self) -> usize {
+        self.overwritten.load(Ordering::Relaxed)
+    }
+
+    /// Enqueues even when the ring is full by evicting the oldest item,
+    /// which is returned, so the producer never has to wait.
+    ///
+    /// Eviction dequeues on the producer's thread, so concurrent consumers
+    /// must use the `multi_*` dequeue operations.
+    #[inline]
+    pub fn force_enqueue(&self, value: T) -> Option<T> {
+        let mut value = value;
+        let mut evicted = None;
+        loop {
+            match self.multi_enqueue(value) {
+                None => return evicted,
+                Some(moved) => value = moved,
+            }
+            // racing producers may refill the freed slot, in which case the
+            // item evicted before is dropped in favour of the newer one
+            if let Some(oldest) = self.multi_dequeue() {
+                self.overwritten.fetch_add(1, Ordering::Relaxed);
+                evicted = Some(oldest);
+            }
         }
     }
 
+    /// # Safety
+    ///
+    /// The caller must guarantee that no other thread enqueues into the
+    /// ring concurrently.
     #[inline]
-    pub fn single_enqueue(&mut self, value: T) -> Option<T> {
+    pub unsafe fn single_enqueue(&self, value: T) -> Option<T> {
         let head = self.prod.front();
         let next = self.prod.next(head);
 
-        if self.cons.reached(next) {
+        if head.wrapping_sub(self.cons.back()) == self.prod.size() {
             return Some(value);
         }
             
         self.prod.head.store(next, Ordering::Release);
 
-        self.inner.write(head as usize, value);
+        self.inner.write(self.prod.index(head), value);
         
         self.prod.tail.store(next, Ordering::Release);
         
         None
     }
 
+    /// # Safety
+    ///
+    /// The caller must guarantee that no other thread dequeues from the
+    /// ring concurrently.
     #[inline]
-    pub fn single_dequeue(&mut self) -> Option<T> {
+    pub unsafe fn single_dequeue(&self) -> Option<T> {
         let head = self.cons.front();
         let next = self.cons.next(head);
 
@@ -58,7 +215,7 @@ impl<T: Debug> Ring<T> {
             
         self.cons.head.store(next, Ordering::Release);
 
-        let value = self.inner.read(head as usize);
+        let value = unsafe { self.inner.read(self.cons.index(head)) };
         
         self.cons.tail.store(next, Ordering::Release);
 
@@ -66,7 +223,7 @@ impl<T: Debug> Ring<T> {
     }
     
     #[inline]
-    pub fn multi_enqueue(&mut self, value: T) -> Option<T> {
+    pub fn multi_enqueue(&self, value: T) -> Option<T> {
         let mut head : u32;
         let mut next : u32;
         let mut tail : u32;
@@ -75,34 +232,32 @@ impl<T: Debug> Ring<T> {
             head = self.prod.front();
             tail = self.cons.back();
             
-            compiler_fence(Ordering::SeqCst);
-
             next = self.prod.next(head);
 
-            if next == tail {
+            let used = head.wrapping_sub(tail);
+            if used == self.prod.size() {
                 return Some(value);
             }
             
-            if self.prod.exchange_front(head, next) { 
+            // a stale head behind the consumer tail reads as more than full
+            if used < self.prod.size() && self.prod.exchange_front(head, next) { 
                 break;
             }
             
-            spin_loop_hint();
+            spin_loop();
         };
 
-        self.inner.write(head as usize, value);
-        
-        compiler_fence(Ordering::SeqCst);
+        self.inner.write(self.prod.index(head), value);
         
         while !self.prod.exchange_back(head, next) {
-            spin_loop_hint();
+            spin_loop();
         }
         
         None
     }
 
     #[inline]
-    pub fn multi_dequeue(&mut self) -> Option<T> {
+    pub fn multi_dequeue(&self) -> Option<T> {
         let mut head : u32;
         let mut next : u32;
         let mut tail : u32;
@@ -111,8 +266,6 @@ impl<T: Debug> Ring<T> {
             head = self.cons.front();
             tail = self.prod.back();
             
-            compiler_fence(Ordering::SeqCst);
-
             next = self.cons.next(head);
             
             if head == tail {
@@ -123,114 +276,759 @@ impl<T: Debug> Ring<T> {
                 break
             }
             
-            spin_loop_hint();
+            spin_loop();
         };
 
-        let value = self.inner.read(head as usize);
-        
-        compiler_fence(Ordering::SeqCst);
+        let value = unsafe { self.inner.read(self.cons.index(head)) };
         
         while !self.cons.exchange_back(head, next) {
-            spin_loop_hint();
+            spin_loop();
         }
 
         Some(value)
     }
+
+    /// Lends the next free slot to be built in place, it is published when
+    /// the guard is committed or dropped after being initialised.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that no other thread enqueues into the
+    /// ring concurrently, and must not enqueue itself while the guard is
+    /// alive.
+    #[inline]
+    pub unsafe fn single_reserve(&self) -> Option<WriteGuard<'_, T, S>> {
+        let head = self.prod.front();
+
+        if head.wrapping_sub(self.cons.back()) == self.prod.size() {
+            return None;
+        }
+
+        Some(WriteGuard { ring: self, head, init: false })
+    }
+
+    /// Lends the oldest item in place, its slot is released when the guard
+    /// is dropped.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that no other thread dequeues from the
+    /// ring concurrently, and must not dequeue itself while the guard is
+    /// alive.
+    #[inline]
+    pub unsafe fn single_read_guard(&self) -> Option<ReadGuard<'_, T, S>> {
+        let head = self.cons.front();
+
+        if self.prod.reached(head) {
+            return None;
+        }
+
+        Some(ReadGuard { ring: self, head })
+    }
+
+    /// Moves items from the front of `values` into the ring claiming all
+    /// their slots at once, returns how many were enqueued. The items that
+    /// did not fit stay in `values`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that no other thread enqueues into the
+    /// ring concurrently.
+    #[inline]
+    pub unsafe fn single_enqueue_bulk(&self, values: &mut Vec<T>, mode: Bulk) -> usize {
+        let head = self.prod.front();
+        let free = self.prod.size() - head.wrapping_sub(self.cons.back());
+
+        let n = mode.count(values.len(), free as usize);
+        if n == 0 {
+            return 0;
+        }
+
+        for (i, value) in values.drain(..n).enumerate() {
+            self.inner.write(self.prod.index(head.wrapping_add(i as u32)), value);
+        }
+
+        let next = head.wrapping_add(n as u32);
+        self.prod.head.store(next, Ordering::Release);
+        self.prod.tail.store(next, Ordering::Release);
+
+        n
+    }
+
+    /// Dequeues up to `max` items into `out` claiming all their slots at
+    /// once, returns how many were dequeued.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that no other thread dequeues from the
+    /// ring concurrently.
+    #[inline]
+    pub unsafe fn single_dequeue_bulk(&self, out: &mut Vec<T>, max: usize, mode: Bulk) -> usize {
+        let head = self.cons.front();
+        let avail = self.prod.back().wrapping_sub(head);
+
+        let n = mode.count(max, avail as usize);
+        if n == 0 {
+            return 0;
+        }
+        out.reserve(n);
+
+        let next = head.wrapping_add(n as u32);
+        self.cons.head.store(next, Ordering::Release);
+
+        for i in 0..n as u32 {
+            out.push(unsafe { self.inner.read(self.cons.index(head.wrapping_add(i))) });
+        }
+
+        self.cons.tail.store(next, Ordering::Release);
+
+        n
+    }
+
+    /// Moves items from the front of `values` into the ring with a single
+    /// reservation, returns how many were enqueued. The items that did not
+    /// fit stay in `values`.
+    ///
+    /// The items already exist when their slots are claimed, so nothing can
+    /// fail half way and leave a claimed slot unwritten.
+    #[inline]
+    pub fn multi_enqueue_bulk(&self, values: &mut Vec<T>, mode: Bulk) -> usize {
+        let mut head : u32;
+        let mut next : u32;
+        let mut tail : u32;
+        let mut n : usize;
+
+        loop {
+            head = self.prod.front();
+            tail = self.cons.back();
+
+            let used = head.wrapping_sub(tail);
+            if used <= self.prod.size() {
+                n = mode.count(values.len(), (self.prod.size() - used) as usize);
+                if n == 0 {
+                    return 0;
+                }
+
+                next = head.wrapping_add(n as u32);
+                if self.prod.exchange_front(head, next) {
+                    break;
+                }
+            }
+
+            spin_loop();
+        }
+
+        for (i, value) in values.drain(..n).enumerate() {
+            self.inner.write(self.prod.index(head.wrapping_add(i as u32)), value);
+        }
+
+        while !self.prod.exchange_back(head, next) {
+            spin_loop();
+        }
+
+        n
+    }
+
+    /// Enqueues clones of `values`, see `multi_enqueue_bulk`. The clones
+    /// are made before any slot is claimed.
+    #[inline]
+    pub fn multi_enqueue_slice(&self, values: &[T], mode: Bulk) -> usize
+    where
+        T: Clone,
+    {
+        let count = mode.count(values.len(), self.remaining());
+        if count == 0 {
+            return 0;
+        }
+        let mut values = values[..count].to_vec();
+        self.multi_enqueue_bulk(&mut values, mode)
+    }
+
+    /// Dequeues up to `max` items into `out` with a single reservation,
+    /// returns how many were dequeued.
+    #[inline]
+    pub fn multi_dequeue_bulk(&self, out: &mut Vec<T>, max: usize, mode: Bulk) -> usize {
+        let mut head : u32;
+        let mut next : u32;
+        let mut tail : u32;
+        let mut n : usize;
+
+        // no allocation can fail once slots are claimed
+        out.reserve(cmp::min(max, self.capacity()));
+
+        loop {
+            head = self.cons.front();
+            tail = self.prod.back();
+
+            n = mode.count(max, tail.wrapping_sub(head) as usize);
+            if n == 0 {
+                return 0;
+            }
+
+            next = head.wrapping_add(n as u32);
+            if self.cons.exchange_front(head, next) {
+                break;
+            }
+
+            spin_loop();
+        }
+
+        for i in 0..n as u32 {
+            out.push(unsafe { self.inner.read(self.cons.index(head.wrapping_add(i))) });
+        }
+
+        while !self.cons.exchange_back(head, next) {
+            spin_loop();
+        }
+
+        n
+    }
+}
+
+/// How many items a bulk operation moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bulk {
+    /// All the requested items or none of them.
+    Exact,
+    /// As many of the requested items as possible.
+    Partial,
+}
+
+impl Bulk {
+    #[inline]
+    fn count(self, wanted: usize, available: usize) -> usize {
+        match self {
+            Bulk::Exact if wanted > available => 0,
+            Bulk::Exact => wanted,
+            Bulk::Partial => cmp::min(wanted, available),
+        }
+    }
 }
 
-impl<T> Drop for Ring<T> {
+/// A reserved producer slot, see `Ring::single_reserve`.
+pub struct WriteGuard<'a, T, S: Storage<T> = Buffer<T>> {
+    ring: &'a Ring<T, S>,
+    head: u32,
+    init: bool,
+}
+
+impl<'a, T, S: Storage<T>> WriteGuard<'a, T, S> {
+    /// The slot to build the value in; once fully written call
+    /// `assume_init` so that it gets published.
+    ///
+    /// A value already written is dropped first: the slot may be left
+    /// uninitialised through the returned reference, so it only gets
+    /// published again after another `write` or `assume_init`.
+    #[inline]
+    pub fn slot(&mut self) -> &mut MaybeUninit<T> {
+        self.clear();
+        self.uninit_slot()
+    }
+
+    #[inline]
+    pub fn write(&mut self, value: T) -> &mut T {
+        self.clear();
+        self.init = true;
+        self.uninit_slot().write(value)
+    }
+
+    #[inline]
+    fn uninit_slot(&mut self) -> &mut MaybeUninit<T> {
+        let index = self.ring.prod.index(self.head);
+        unsafe { &mut *self.ring.inner.slot(index) }
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        if self.init {
+            self.init = false;
+            unsafe { ptr::drop_in_place(self.uninit_slot().as_mut_ptr()) };
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The slot must hold a fully initialised `T`.
+    #[inline]
+    pub unsafe fn assume_init(&mut self) {
+        self.init = true;
+    }
+
+    /// Publishes the slot, or gives it back if it was never initialised.
+    #[inline]
+    pub fn commit(self) {}
+}
+
+impl<'a, T, S: Storage<T>> Drop for WriteGuard<'a, T, S> {
+    #[inline]
+    fn drop(&mut self) {
+        if !self.init {
+            return;
+        }
+        let next = self.ring.prod.next(self.head);
+        self.ring.prod.head.store(next, Ordering::Release);
+        self.ring.prod.tail.store(next, Ordering::Release);
+    }
+}
+
+/// A borrowed consumer slot, see `Ring::single_read_guard`.
+///
+/// It lends out `&T`, so it is only `Sync` when `T` is:
+///
+/// ```compile_fail,E0277
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<ring::ring::ReadGuard<'static, std::cell::Cell<u8>>>();
+/// ```
+pub struct ReadGuard<'a, T, S: Storage<T> = Buffer<T>> {
+    ring: &'a Ring<T, S>,
+    head: u32,
+}
+
+impl<'a, T, S: Storage<T>> ReadGuard<'a, T, S> {
+    /// Moves the item out and releases the slot.
+    #[inline]
+    pub fn take(self) -> T {
+        let value = unsafe { self.ring.inner.read(self.ring.cons.index(self.head)) };
+        self.release();
+        mem::forget(self);
+        value
+    }
+
+    #[inline]
+    fn release(&self) {
+        let next = self.ring.cons.next(self.head);
+        self.ring.cons.head.store(next, Ordering::Release);
+        self.ring.cons.tail.store(next, Ordering::Release);
+    }
+}
+
+impl<'a, T, S: Storage<T>> Deref for ReadGuard<'a, T, S> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { self.ring.inner.at(self.ring.cons.index(self.head)) }
+    }
+}
+
+impl<'a, T, S: Storage<T>> Drop for ReadGuard<'a, T, S> {
+    #[inline]
+    fn drop(&mut self) {
+        let index = self.ring.cons.index(self.head);
+        unsafe { self.ring.inner.drop_at(index) };
+        self.release();
+    }
+}
+
+// cursors and occupancy only, the slots themselves are never read
+impl<T, S: Storage<T>> Debug for Ring<T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ring")
+            .field("prod", &self.prod)
+            .field("cons", &self.cons)
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .field("overwritten", &self.overwritten())
+            .finish()
+    }
+}
+
+impl<T, S: Storage<T>> Drop for Ring<T, S> {
     fn drop(&mut self) {
-        for i in 0..self.inner.size {
-            self.inner.drop_at(i)
+        // with `&mut self` nothing is half way through: the live items are
+        // exactly the published ones not yet released by a consumer
+        let mut pos = self.cons.back();
+        let tail = self.prod.back();
+        while pos != tail {
+            unsafe { self.inner.drop_at(self.cons.index(pos)) };
+            pos = pos.wrapping_add(1);
         }
     }
 }
 
-unsafe impl<T> Sync for Ring<T> {}
-unsafe impl<T> Send for Ring<T> {}
+// items are moved between threads, the ring itself never hands out a
+// shared reference to one: `T: Send` is enough for both
+unsafe impl<T: Send, S: Storage<T> + Send> Sync for Ring<T, S> {}
+unsafe impl<T: Send, S: Storage<T> + Send> Send for Ring<T, S> {}
+
+// a `ReadGuard` does, sharing the guard shares the item
+unsafe impl<'a, T: Send + Sync, S: Storage<T> + Send> Sync for ReadGuard<'a, T, S> {}
 
 #[cfg(test)]
 mod tests {
     use std::thread;
     use std::sync::Arc;
-    use std::sync::atomic::{AtomicUsize, Ordering, spin_loop_hint};
-    use std::cell::UnsafeCell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use super::*;
+    use crate::stamped::StampedRing;
+    use crate::test_util::{Counted, Drops};
 
     #[test]
     fn test_constructor() {
-        let mut ring : Ring<u8> = Ring::new(8);
-        assert_eq!(ring.single_dequeue(), None);
+        let ring : Ring<u8> = Ring::new(8);
+        assert_eq!(unsafe { ring.single_dequeue() }, None);
         assert_eq!(ring.multi_dequeue(), None);
     }
 
     #[test]
     fn test_single() {
-        let mut ring : Ring<u8> = Ring::new(2);
+        let ring : Ring<u8> = Ring::new(2);
         
-        assert_eq!(ring.single_enqueue(0), None);
-        assert_eq!(ring.single_enqueue(1), None);
-        assert_eq!(ring.single_enqueue(2), None);
-        assert_eq!(ring.single_enqueue(3), Some(3));
+        unsafe {
+            assert_eq!(ring.single_enqueue(0), None);
+            assert_eq!(ring.single_enqueue(1), None);
+            assert_eq!(ring.single_enqueue(2), None);
+            assert_eq!(ring.single_enqueue(3), None);
+            assert_eq!(ring.single_enqueue(4), Some(4));
 
-        assert_eq!(ring.single_dequeue(), Some(0));
-        assert_eq!(ring.single_dequeue(), Some(1));
-        assert_eq!(ring.single_dequeue(), Some(2));
-        assert_eq!(ring.single_dequeue(), None);
+            assert_eq!(ring.single_dequeue(), Some(0));
+            assert_eq!(ring.single_dequeue(), Some(1));
+            assert_eq!(ring.single_dequeue(), Some(2));
+            assert_eq!(ring.single_dequeue(), Some(3));
+            assert_eq!(ring.single_dequeue(), None);
+        }
     }
 
     #[test]
     fn test_multi() {
-        let mut ring : Ring<u8> = Ring::new(2);
+        let ring : Ring<u8> = Ring::new(2);
         
         assert_eq!(ring.multi_enqueue(0), None);
         assert_eq!(ring.multi_enqueue(1), None);
         assert_eq!(ring.multi_enqueue(2), None);
-        assert_eq!(ring.multi_enqueue(3), Some(3));
+        assert_eq!(ring.multi_enqueue(3), None);
+        assert_eq!(ring.multi_enqueue(4), Some(4));
 
         assert_eq!(ring.multi_dequeue(), Some(0));
         assert_eq!(ring.multi_dequeue(), Some(1));
         assert_eq!(ring.multi_dequeue(), Some(2));
+        assert_eq!(ring.multi_dequeue(), Some(3));
         assert_eq!(ring.multi_dequeue(), None);
     }
 
+    #[test]
+    fn test_len() {
+        let ring : Ring<u8> = Ring::new(2);
+        assert_eq!(ring.capacity(), 4);
+        assert_eq!(ring.len(), 0);
+        assert!(ring.is_empty());
+        assert_eq!(ring.remaining(), 4);
+
+        ring.multi_enqueue(0);
+        ring.multi_enqueue(1);
+        assert_eq!(ring.len(), 2);
+        assert_eq!(ring.remaining(), 2);
+        assert!(!ring.is_empty() && !ring.is_full());
 
-    struct Wrapper<T> {
-        inner: Arc<UnsafeCell<Ring<T>>>,
+        ring.multi_enqueue(2);
+        ring.multi_enqueue(3);
+        assert!(ring.is_full());
+        assert_eq!(ring.remaining(), 0);
+
+        ring.multi_dequeue();
+        assert_eq!(ring.len(), 3);
+        assert_eq!(ring.remaining(), 1);
     }
-    
-    impl<T: Debug> Wrapper<T> {
-        fn new(size: usize) -> Self {
-            let inner = Arc::new(UnsafeCell::new(Ring::new(size)));
-            Wrapper{ inner }
+
+    #[test]
+    fn test_debug() {
+        // closures have no Debug, the ring only reports its cursors
+        let ring : Ring<Box<dyn Fn() -> u8>> = Ring::new(2);
+        ring.multi_enqueue(Box::new(|| 1));
+        assert_eq!(ring.multi_dequeue().map(|f| f()), Some(1));
+        ring.multi_enqueue(Box::new(|| 2));
+
+        assert_eq!(
+            format!("{:?}", ring),
+            "Ring { prod: Cursor { head: 2, tail: 2, size: 4, mask: 3 }, \
+             cons: Cursor { head: 1, tail: 1, size: 4, mask: 3 }, \
+             len: 1, capacity: 4, overwritten: 0 }"
+        );
+        assert!(format!("{:?}", StaticRing::<u8, 4>::new()).starts_with("StaticRing(Ring {"));
+    }
+
+    #[test]
+    fn test_has_free_slot() {
+        let ring : Ring<u8> = Ring::new(2);
+        assert!(ring.has_free_slot());
+        for i in 0..4 {
+            assert_eq!(ring.multi_enqueue(i), None);
         }
+        assert!(!ring.has_free_slot());
+
+        // a consumer claimed a slot but has not released it yet
+        ring.cons.head.store(1, Ordering::Release);
+        assert!(!ring.is_full());
+        assert!(!ring.has_free_slot());
+        assert_eq!(ring.multi_enqueue(4), Some(4));
+
+        ring.cons.tail.store(1, Ordering::Release);
+        assert!(ring.has_free_slot());
+        assert_eq!(ring.multi_enqueue(4), None);
+    }
+
+    #[test]
+    fn test_bulk() {
+        let ring : Ring<u8> = Ring::new(3);
+
+        assert_eq!(ring.multi_enqueue_slice(&[0, 1, 2, 3, 4, 5], Bulk::Exact), 6);
+        assert_eq!(ring.multi_enqueue_slice(&[6, 7, 8], Bulk::Exact), 0);
+        let mut rest = vec![6, 7, 8];
+        assert_eq!(ring.multi_enqueue_bulk(&mut rest, Bulk::Partial), 2);
+        assert_eq!(rest, vec![8]);
+        assert!(ring.is_full());
+
+        let mut out = Vec::new();
+        assert_eq!(ring.multi_dequeue_bulk(&mut out, 16, Bulk::Exact), 0);
+        assert_eq!(ring.multi_dequeue_bulk(&mut out, 3, Bulk::Exact), 3);
+        assert_eq!(out, vec![0, 1, 2]);
+        assert_eq!(ring.multi_dequeue_bulk(&mut out, 16, Bulk::Partial), 5);
+        assert_eq!(out, vec![0, 1, 2, 3, 4, 5, 6, 7]);
+        assert!(ring.is_empty());
 
-        fn single_enqueue(&self, v: T) -> Option<T> { 
-            self.ring().single_enqueue(v)
+        unsafe {
+            let mut values = (10..20).collect();
+            assert_eq!(ring.single_enqueue_bulk(&mut values, Bulk::Exact), 0);
+            assert_eq!(ring.single_enqueue_bulk(&mut values, Bulk::Partial), 8);
+            assert_eq!(values, vec![18, 19]);
+
+            out.clear();
+            assert_eq!(ring.single_dequeue_bulk(&mut out, 4, Bulk::Exact), 4);
+            assert_eq!(ring.single_dequeue_bulk(&mut out, 8, Bulk::Exact), 0);
+            assert_eq!(ring.single_dequeue_bulk(&mut out, 8, Bulk::Partial), 4);
+            assert_eq!(out, (10..18).collect::<Vec<_>>());
         }
+    }
+
+    #[test]
+    fn test_reserve() {
+        let ring : Ring<[u8; 64]> = Ring::new(2);
+
+        unsafe {
+            let mut guard = ring.single_reserve().unwrap();
+            let slot = guard.slot().as_mut_ptr() as *mut u8;
+            for i in 0..64 {
+                slot.add(i).write(i as u8);
+            }
+            guard.assume_init();
+            guard.commit();
+
+            // never initialised, so never published
+            drop(ring.single_reserve().unwrap());
+            assert_eq!(ring.len(), 1);
 
-        fn single_dequeue(&self) -> Option<T> { 
-            self.ring().single_dequeue()
-        }        
+            for i in 1..4 {
+                ring.single_reserve().unwrap().write([i; 64]);
+            }
+            assert!(ring.single_reserve().is_none());
 
-        fn multi_enqueue(&self, v: T) -> Option<T> { 
-            self.ring().multi_enqueue(v)
+            let guard = ring.single_read_guard().unwrap();
+            assert_eq!(guard[63], 63);
+            drop(guard);
+
+            assert_eq!(ring.single_read_guard().unwrap().take(), [1; 64]);
+            assert_eq!(ring.len(), 2);
+            assert_eq!(ring.single_read_guard().unwrap()[0], 2);
+            assert_eq!(ring.single_dequeue(), Some([3; 64]));
+            assert!(ring.single_read_guard().is_none());
         }
-        fn multi_dequeue(&self) -> Option<T> { 
-            self.ring().multi_dequeue()
-        }                
+    }
 
-        fn ring(&self) -> &mut Ring<T> {
-            unsafe { &mut (*self.inner.get()) }
+    #[test]
+    fn test_drop_live() {
+        let drops = Drops::new();
+
+        // nothing was ever written
+        drop(Ring::<Counted>::new(2));
+        assert_eq!(drops.count(), 0);
+
+        let ring : Ring<Counted> = Ring::new(2);
+        for _ in 0..4 {
+            assert!(ring.multi_enqueue(drops.item()).is_none());
         }
-        
-        fn clone(&self) -> Self {
-            Wrapper{ inner: Arc::clone(&self.inner) }
+        drop(ring.multi_dequeue());
+        drop(ring.multi_dequeue());
+        assert_eq!(drops.count(), 2);
+
+        // wrap around so the live items straddle the end of the buffer
+        assert!(ring.multi_enqueue(drops.item()).is_none());
+        drop(ring);
+        assert_eq!(drops.count(), 5);
+    }
+
+    #[test]
+    fn test_drop_guards() {
+        let drops = Drops::new();
+
+        let ring : Ring<Counted> = Ring::new(2);
+        unsafe {
+            drop(ring.single_reserve().unwrap());
+            assert_eq!(drops.count(), 0);
+
+            let mut guard = ring.single_reserve().unwrap();
+            guard.write(drops.item());
+            guard.write(drops.item());
+            assert_eq!(drops.count(), 1);
+            guard.commit();
+            assert!(ring.single_enqueue(drops.item()).is_none());
+
+            drop(ring.single_read_guard().unwrap());
+            assert_eq!(drops.count(), 2);
+
+            let value = ring.single_read_guard().unwrap().take();
+            assert_eq!(drops.count(), 2);
+            drop(value);
+            assert_eq!(drops.count(), 3);
+        }
+
+        let mut out = Vec::new();
+        ring.multi_enqueue_slice(&[drops.item(), drops.item()], Bulk::Exact);
+        assert_eq!(drops.count(), 5);
+        ring.multi_dequeue_bulk(&mut out, 1, Bulk::Exact);
+        drop(ring);
+        assert_eq!(drops.count(), 6);
+        drop(out);
+        assert_eq!(drops.count(), 7);
+    }
+
+    #[test]
+    fn test_slot_after_write() {
+        let drops = Drops::new();
+
+        let ring : Ring<Counted> = Ring::new(2);
+        unsafe {
+            let mut guard = ring.single_reserve().unwrap();
+            guard.write(drops.item());
+            // the written value is gone, the slot is up for grabs again
+            *guard.slot() = MaybeUninit::uninit();
+            assert_eq!(drops.count(), 1);
+            guard.commit();
+        }
+        assert!(ring.is_empty());
+        assert!(ring.multi_dequeue().is_none());
+    }
+
+    #[test]
+    fn test_force_enqueue() {
+        let ring : Ring<u8> = Ring::new(2);
+
+        for i in 0..4 {
+            assert_eq!(ring.force_enqueue(i), None);
+        }
+        assert_eq!(ring.overwritten(), 0);
+
+        assert_eq!(ring.force_enqueue(4), Some(0));
+        assert_eq!(ring.force_enqueue(5), Some(1));
+        assert_eq!(ring.overwritten(), 2);
+        assert!(ring.is_full());
+
+        for i in 2..6 {
+            assert_eq!(ring.multi_dequeue(), Some(i));
+        }
+        assert_eq!(ring.multi_dequeue(), None);
+    }
+
+    #[test]
+    fn test_force_enqueue_concurrent() {
+        const N : usize = 10_000;
+
+        let ring : Arc<Ring<usize>> = Arc::new(Ring::new(2));
+        let received = Arc::new(AtomicUsize::new(0));
+
+        let prods : Vec<_> = (0..2).map(|_| {
+            let r = Arc::clone(&ring);
+            thread::spawn(move || (0..N).for_each(|i| { r.force_enqueue(i); }))
+        }).collect();
+        let cons = {
+            let r = Arc::clone(&ring);
+            let received = Arc::clone(&received);
+            thread::spawn(move || {
+                for _ in 0..N {
+                    if r.multi_dequeue().is_some() {
+                        received.fetch_add(1, Ordering::SeqCst);
+                    }
+                    thread::yield_now();
+                }
+            })
+        };
+
+        prods.into_iter().for_each(|h| h.join().unwrap());
+        cons.join().unwrap();
+
+        // every item was either received, evicted, or is still queued
+        let received = received.load(Ordering::SeqCst);
+        assert_eq!(received + ring.overwritten() + ring.len(), 2*N);
+    }
+
+    #[cfg(not(loom))]
+    static STATIC : StaticRing<usize, 16> = StaticRing::new();
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_static() {
+        const N : usize = 1_000;
+
+        let prod = thread::spawn(|| {
+            for i in 0..N {
+                let mut val = i;
+                while let Some(v) = STATIC.multi_enqueue(val) {
+                    val = v;
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut sum = 0;
+        for _ in 0..N {
+            loop {
+                match STATIC.multi_dequeue() {
+                    Some(v) => { sum += v; break },
+                    None => thread::yield_now(),
+                }
+            }
         }
+        prod.join().unwrap();
+
+        assert_eq!(sum, (0..N).sum());
+        assert_eq!(STATIC.capacity(), 16);
+        assert!(STATIC.is_empty());
     }
 
-    unsafe impl<T> Sync for Wrapper<T> { }
-    unsafe impl<T> Send for Wrapper<T> { }
+    #[test]
+    fn test_static_drop() {
+        let drops = Drops::new();
+
+        let ring : StaticRing<Counted, 4> = StaticRing::new();
+        for _ in 0..3 {
+            assert!(ring.multi_enqueue(drops.item()).is_none());
+        }
+        drop(ring.multi_dequeue());
+        drop(ring);
+        assert_eq!(drops.count(), 3);
+    }
+
+    #[test]
+    fn test_wrap() {
+        let ring : Ring<u32> = Ring::new(2);
+        let start = u32::MAX - 1;
+        for cursor in &[&ring.prod, &ring.cons] {
+            cursor.head.store(start, Ordering::Release);
+            cursor.tail.store(start, Ordering::Release);
+        }
+
+        for round in 0..4 {
+            for i in 0..4 {
+                assert_eq!(ring.multi_enqueue(round * 4 + i), None);
+            }
+            assert_eq!(ring.multi_enqueue(0), Some(0));
+            assert_eq!(ring.len(), 4);
+
+            for i in 0..4 {
+                assert_eq!(ring.multi_dequeue(), Some(round * 4 + i));
+            }
+            assert_eq!(ring.multi_dequeue(), None);
+        }
+    }
 
     macro_rules! sum {
         ($n:expr, $ty:ty ) => {
@@ -238,8 +1036,20 @@ mod tests {
         };
     }
 
+    // runs on `Ring` unless another ring type is given
     macro_rules! ring_test {
         ( $ty:ty,
+          $PRODUCERS:expr,
+          $CONSUMERS:expr,
+          $N:expr, 
+          $bitsize:expr,
+          $prod:ident,
+          $cons:ident
+          ) => {
+            ring_test!(Ring; $ty, $PRODUCERS, $CONSUMERS, $N, $bitsize, $prod, $cons)
+        };
+        ( $ring:ident;
+          $ty:ty,
           $PRODUCERS:expr,
           $CONSUMERS:expr,
           $N:expr, 
@@ -254,7 +1064,7 @@ mod tests {
             const CN : usize = (N*PRODUCERS/CONSUMERS as usize);
             let result = Arc::new(AtomicUsize::new(0));
 
-            let ring = Wrapper::new($bitsize);
+            let ring : Arc<$ring<$ty>> = Arc::new($ring::new($bitsize));
             let mut prods = Vec::with_capacity(PRODUCERS);
             let mut cons = Vec::with_capacity(CONSUMERS);
             
@@ -264,15 +1074,17 @@ mod tests {
 
             // producers
             for i in 0..PRODUCERS {
-                let r = ring.clone();
+                let r = Arc::clone(&ring);
                 prods.push(thread::spawn(move || {
                     for j in 0..N {
                         let mut val = j;
                         'inner: loop {
-                            match r.$prod(val as $ty) {
+                            #[allow(unused_unsafe)]
+                            let res = unsafe { r.$prod(val as $ty) };
+                            match res {
                                 Some(v) => { 
                                     val = v as usize; 
-                                    spin_loop_hint();
+                                    thread::yield_now();
                                     continue
                                 },
                                 None => { 
@@ -288,18 +1100,20 @@ mod tests {
             
             // consumers
             for i in 0..CONSUMERS {
-                let r = ring.clone();
+                let r = Arc::clone(&ring);
                 let result = Arc::clone(&result);
                 cons.push(thread::spawn(move || {
                     for _ in 0..CN {
                         'inner: loop {
-                            match r.$cons() {
+                            #[allow(unused_unsafe)]
+                            let res = unsafe { r.$cons() };
+                            match res {
                                 Some(v) => { 
                                     result.fetch_add(v as usize, Ordering::SeqCst);
                                     break 'inner 
                                 },
                                 None => {
-                                    spin_loop_hint();
+                                    thread::yield_now();
                                     continue;
                                 }
                             }
@@ -344,4 +1158,76 @@ mod tests {
         ring_test!(usize, 4, 4, 1_000, 8, multi_enqueue, multi_dequeue);
         ring_test!(usize, 8, 8, 1_000, 8, multi_enqueue, multi_dequeue);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_stamped_single_producer_single_consumer() {
+        ring_test!(StampedRing; usize, 1, 1, 100_000, 2, single_enqueue, single_dequeue);
+    }
+
+    #[test]
+    fn test_stamped_single_producer_multi_consumer() {
+        ring_test!(StampedRing; usize, 1, 2, 1_000, 8, multi_enqueue, multi_dequeue);
+        ring_test!(StampedRing; usize, 2, 4, 1_000, 8, multi_enqueue, multi_dequeue);
+    }
+
+    #[test]
+    fn test_stamped_multi_producer_single_consumer() {
+        ring_test!(StampedRing; usize, 2, 1, 1_000, 8, multi_enqueue, multi_dequeue);
+        ring_test!(StampedRing; usize, 4, 2, 1_000, 8, multi_enqueue, multi_dequeue);
+    }
+
+    #[test]
+    fn test_stamped_multi_producer_multi_consumer() {
+        ring_test!(StampedRing; usize, 2, 2, 1_000, 8, multi_enqueue, multi_dequeue);
+        ring_test!(StampedRing; usize, 4, 4, 1_000, 8, multi_enqueue, multi_dequeue);
+        ring_test!(StampedRing; usize, 8, 8, 1_000, 8, multi_enqueue, multi_dequeue);
+    }
+
+    #[test]
+    fn test_bulk_multi_producer_multi_consumer() {
+        const PRODUCERS : usize = 4;
+        const CONSUMERS : usize = 4;
+        const N : usize = 1_000;
+
+        let ring : Arc<Ring<usize>> = Arc::new(Ring::new(4));
+        let result = Arc::new(AtomicUsize::new(0));
+        let received = Arc::new(AtomicUsize::new(0));
+
+        let prods : Vec<_> = (0..PRODUCERS).map(|_| {
+            let r = Arc::clone(&ring);
+            thread::spawn(move || {
+                let mut values : Vec<_> = (0..N).collect();
+                while !values.is_empty() {
+                    if r.multi_enqueue_bulk(&mut values, Bulk::Partial) == 0 {
+                        thread::yield_now();
+                    }
+                }
+            })
+        }).collect();
+
+        let cons : Vec<_> = (0..CONSUMERS).map(|_| {
+            let r = Arc::clone(&ring);
+            let result = Arc::clone(&result);
+            let received = Arc::clone(&received);
+            thread::spawn(move || {
+                let mut out = Vec::new();
+                while received.load(Ordering::Acquire) < N*PRODUCERS {
+                    out.clear();
+                    let n = r.multi_dequeue_bulk(&mut out, 5, Bulk::Partial);
+                    if n == 0 {
+                        thread::yield_now();
+                        continue;
+                    }
+                    result.fetch_add(out.iter().sum(), Ordering::SeqCst);
+                    received.fetch_add(n, Ordering::SeqCst);
+                }
+            })
+        }).collect();
+
+        prods.into_iter().for_each(|h| h.join().unwrap());
+        cons.into_iter().for_each(|h| h.join().unwrap());
+
+        assert_eq!(received.load(Ordering::Acquire), N*PRODUCERS);
+        assert_eq!(result.load(Ordering::Acquire), PRODUCERS*sum!(N, usize));
+    }
+}
@@ -0,0 +1,315 @@
+use std::sync::Arc;
+use std::task::{Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+use crate::channel::{Backend, Flavor, Receiver, Sender};
+use crate::error::{SelectTimeoutError, TrySelectError};
+use crate::wait::WaitStrategy;
+
+// one operation of a `Select`, type erased
+trait Arm {
+    fn is_ready(&self) -> bool;
+    fn register(&self, waker: &Waker);
+    fn deregister(&self, waker: &Waker);
+}
+
+struct RecvOp<'a, T, C: Flavor, W: WaitStrategy, B: Backend<T>>(&'a Receiver<T, C, W, B>);
+
+struct SendOp<'a, T, P: Flavor, W: WaitStrategy, B: Backend<T>>(&'a Sender<T, P, W, B>);
+
+impl<'a, T, C: Flavor, W: WaitStrategy, B: Backend<T>> Arm for RecvOp<'a, T, C, W, B> {
+    #[inline]
+    fn is_ready(&self) -> bool {
+        self.0.is_ready()
+    }
+
+    #[inline]
+    fn register(&self, waker: &Waker) {
+        self.0.register(waker)
+    }
+
+    #[inline]
+    fn deregister(&self, waker: &Waker) {
+        self.0.deregister(waker)
+    }
+}
+
+impl<'a, T, P: Flavor, W: WaitStrategy, B: Backend<T>> Arm for SendOp<'a, T, P, W, B> {
+    #[inline]
+    fn is_ready(&self) -> bool {
+        self.0.is_ready()
+    }
+
+    #[inline]
+    fn register(&self, waker: &Waker) {
+        self.0.register(waker)
+    }
+
+    #[inline]
+    fn deregister(&self, waker: &Waker) {
+        self.0.deregister(waker)
+    }
+}
+
+struct Unparker(Thread);
+
+impl Wake for Unparker {
+    #[inline]
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    #[inline]
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Waits on several channel operations at once.
+///
+/// Each added operation gets an index, the select functions return the
+/// index of one that is ready: a receive that has an item or whose
+/// senders are all gone, a send that has a free slot or whose receivers
+/// are all gone. The operation itself is then completed with the
+/// non-blocking `try_recv` or `try_send`; with `Multi` handles another
+/// thread may have won the item or slot in between, in which case
+/// `TryRecvError::Empty` or `TrySendError::Full` means select again.
+///
+/// When several operations are ready, the search starts right after the
+/// last one selected, so a busy channel cannot starve the others.
+///
+/// ```
+/// use ring::channel::{channel, Select};
+///
+/// let (_ctrl_tx, ctrl_rx) = channel::<&str>(2);
+/// let (data_tx, data_rx) = channel::<u64>(4);
+/// data_tx.send(42).unwrap();
+///
+/// let mut sel = Select::new();
+/// let _ctrl = sel.recv(&ctrl_rx);
+/// let data = sel.recv(&data_rx);
+///
+/// let index = sel.select();
+/// assert_eq!(index, data);
+/// assert_eq!(data_rx.try_recv(), Ok(42));
+/// ```
+pub struct Select<'a> {
+    arms: Vec<Box<dyn Arm + 'a>>,
+    next: usize,
+    waker: Option<Waker>,
+}
+
+impl<'a> Select<'a> {
+    #[inline]
+    pub fn new() -> Self {
+        Select { arms: Vec::new(), next: 0, waker: None }
+    }
+
+    /// Adds a receive operation and returns its index.
+    pub fn recv<T, C, W, B>(&mut self, receiver: &'a Receiver<T, C, W, B>) -> usize
+    where
+        T: 'a,
+        C: Flavor + 'a,
+        W: WaitStrategy + 'a,
+        B: Backend<T> + 'a,
+    {
+        self.arms.push(Box::new(RecvOp(receiver)));
+        self.arms.len() - 1
+    }
+
+    /// Adds a send operation and returns its index.
+    pub fn send<T, P, W, B>(&mut self, sender: &'a Sender<T, P, W, B>) -> usize
+    where
+        T: 'a,
+        P: Flavor + 'a,
+        W: WaitStrategy + 'a,
+        B: Backend<T> + 'a,
+    {
+        self.arms.push(Box::new(SendOp(sender)));
+        self.arms.len() - 1
+    }
+
+    /// Returns a ready operation without blocking.
+    #[inline]
+    pub fn try_select(&mut self) -> Result<usize, TrySelectError> {
+        self.poll().ok_or(TrySelectError)
+    }
+
+    /// Blocks until an operation is ready.
+    ///
+    /// # Panics
+    ///
+    /// If no operation was added.
+    #[inline]
+    pub fn select(&mut self) -> usize {
+        self.select_until(None).unwrap()
+    }
+
+    /// Blocks until an operation is ready or `timeout` elapsed.
+    #[inline]
+    pub fn select_timeout(&mut self, timeout: Duration) -> Result<usize, SelectTimeoutError> {
+        self.select_until(Instant::now().checked_add(timeout))
+    }
+
+    /// Blocks until an operation is ready or `deadline` is reached.
+    #[inline]
+    pub fn select_deadline(&mut self, deadline: Instant) -> Result<usize, SelectTimeoutError> {
+        self.select_until(Some(deadline))
+    }
+
+    fn select_until(&mut self, deadline: Option<Instant>) -> Result<usize, SelectTimeoutError> {
+        assert!(!self.arms.is_empty(), "select needs at least one operation");
+
+        if let Some(index) = self.poll() {
+            return Ok(index);
+        }
+
+        let waker = self.waker
+            .get_or_insert_with(|| Waker::from(Arc::new(Unparker(thread::current()))))
+            .clone();
+        let result = loop {
+            // register before looking again, progress made in between is
+            // then either seen or unparks us
+            for arm in &self.arms {
+                arm.register(&waker);
+            }
+            if let Some(index) = self.poll() {
+                break Ok(index);
+            }
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break Err(SelectTimeoutError);
+                    }
+                    thread::park_timeout(deadline - now);
+                },
+                None => thread::park(),
+            }
+        };
+
+        // the channels that did not wake us still hold the waker
+        for arm in &self.arms {
+            arm.deregister(&waker);
+        }
+        result
+    }
+
+    // first ready operation, starting after the one selected last
+    fn poll(&mut self) -> Option<usize> {
+        let count = self.arms.len();
+        let index = (0..count)
+            .map(|offset| (self.next + offset) % count)
+            .find(|&index| self.arms[index].is_ready())?;
+        self.next = index + 1;
+        Some(index)
+    }
+}
+
+impl<'a> Default for Select<'a> {
+    #[inline]
+    fn default() -> Self {
+        Select::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::channel::*;
+    use super::*;
+
+    #[test]
+    fn test_try_select() {
+        let (tx0, rx0) = spsc::<u8>(2);
+        let (tx1, rx1) = mpmc::<u8>(2);
+
+        let mut sel = Select::new();
+        let r0 = sel.recv(&rx0);
+        let r1 = sel.recv(&rx1);
+        assert_eq!(sel.try_select(), Err(TrySelectError));
+
+        tx1.send(1).unwrap();
+        assert_eq!(sel.try_select(), Ok(r1));
+        tx0.send(0).unwrap();
+        assert_eq!(sel.try_select(), Ok(r0));
+
+        // a disconnected channel is always ready
+        assert_eq!(rx0.try_recv(), Ok(0));
+        assert_eq!(rx1.try_recv(), Ok(1));
+        drop(tx0);
+        assert_eq!(sel.try_select(), Ok(r0));
+        assert_eq!(sel.try_select(), Ok(r0));
+    }
+
+    #[test]
+    fn test_select_send() {
+        let (tx, rx) = spsc::<u8>(2);
+        (0..4).for_each(|i| tx.send(i).unwrap());
+
+        let mut sel = Select::new();
+        let s = sel.send(&tx);
+        assert_eq!(sel.try_select(), Err(TrySelectError));
+
+        assert_eq!(rx.recv(), Ok(0));
+        assert_eq!(sel.try_select(), Ok(s));
+    }
+
+    #[test]
+    fn test_fairness() {
+        let (tx0, rx0) = spsc::<u8>(2);
+        let (tx1, rx1) = spsc::<u8>(2);
+        tx0.send(0).unwrap();
+        tx1.send(1).unwrap();
+
+        let mut sel = Select::new();
+        let r0 = sel.recv(&rx0);
+        let r1 = sel.recv(&rx1);
+
+        // both stay ready, so selects alternate between them
+        let picks = (0..4).map(|_| sel.select()).collect::<Vec<_>>();
+        assert_eq!(picks, [r0, r1, r0, r1]);
+    }
+
+    #[test]
+    fn test_select_timeout() {
+        let (_tx, rx) = channel::<u8>(2);
+
+        let mut sel = Select::new();
+        sel.recv(&rx);
+        let start = Instant::now();
+        assert_eq!(sel.select_timeout(Duration::from_millis(20)), Err(SelectTimeoutError));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_select_wakeup() {
+        let (ctrl_tx, ctrl_rx) = spsc::<&str>(2);
+        let (data_tx, data_rx) = mpsc_with::<u64, _>(2, Park::new());
+
+        let handle = thread::spawn(move || {
+            let mut sum = 0;
+            let mut sel = Select::new();
+            let ctrl = sel.recv(&ctrl_rx);
+            let data = sel.recv(&data_rx);
+            loop {
+                let index = sel.select();
+                if index == ctrl {
+                    assert_eq!(ctrl_rx.try_recv(), Ok("stop"));
+                    return sum;
+                }
+                assert_eq!(index, data);
+                sum += data_rx.try_recv().unwrap();
+            }
+        });
+
+        for i in 0..1000 {
+            data_tx.send(i).unwrap();
+        }
+        while !data_tx.is_empty() {
+            thread::yield_now();
+        }
+        ctrl_tx.send("stop").unwrap();
+        assert_eq!(handle.join().unwrap(), 1000 * 999 / 2);
+    }
+}
@@ -0,0 +1,477 @@
+//! Multi-stage pipeline over a single ring, in the style of a Disruptor.
+//!
+//! A producer publishes items through its cursor and every stage has a
+//! consumer cursor of its own that may only advance up to the positions of
+//! the stages it depends on, or of the producer for a stage without
+//! dependencies. All stages work on the slot in place and the producer only
+//! reuses it once every stage has passed it, so a chain like
+//! decode → enrich → persist needs neither a queue nor a copy per stage.
+//!
+//! A stage ordered against every other stage, as in a plain chain, gets the
+//! item mutably; stages that may run side by side on the same slot only get
+//! shared access.
+//!
+//! ```
+//! use ring::sequencer::Pipeline;
+//!
+//! let mut pipeline = Pipeline::new(4);
+//! let decode = pipeline.stage(&[]);
+//! let persist = pipeline.stage(&[decode]);
+//! let (mut producer, mut stages) = pipeline.build();
+//!
+//! producer.publish(1u64);
+//! assert_eq!(stages[decode.index()].try_process(|v| *v *= 10), 1);
+//! assert_eq!(stages[persist.index()].try_inspect(|v| assert_eq!(*v, 10)), 1);
+//! ```
+
+use core::hint::spin_loop;
+use core::mem;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::buffer::{Buffer, Storage};
+use crate::cursor::Cursor;
+
+/// Identifies a stage of a `Pipeline`, by the order it was added in.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct StageId(usize);
+
+impl StageId {
+    /// Index of the stage in the vector returned by `Pipeline::build`.
+    #[inline]
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// Builds the stage graph of a sequencer.
+#[derive(Debug)]
+pub struct Pipeline {
+    log2: usize,
+    deps: Vec<Vec<usize>>,
+}
+
+impl Pipeline {
+    #[inline]
+    pub fn new(log2: usize) -> Self {
+        assert!(log2 > 1, "log2 must give greather than 1");
+        assert!(log2 < 32, "log2 must give less than 32");
+
+        Pipeline { log2, deps: Vec::new() }
+    }
+
+    /// Adds a stage that processes a slot only after all of `deps` did.
+    ///
+    /// Stages can only depend on stages added before them, so the graph is
+    /// always acyclic.
+    pub fn stage(&mut self, deps: &[StageId]) -> StageId {
+        let mut deps : Vec<usize> = deps.iter().map(|dep| dep.0).collect();
+        deps.sort_unstable();
+        deps.dedup();
+        assert!(deps.iter().all(|&dep| dep < self.deps.len()), "unknown stage dependency");
+
+        self.deps.push(deps);
+        StageId(self.deps.len() - 1)
+    }
+
+    /// The producer and one handle per stage, in the order they were added.
+    pub fn build<T>(self) -> (Producer<T>, Vec<Stage<T>>) {
+        assert!(!self.deps.is_empty(), "a pipeline needs at least one stage");
+        assert!(mem::size_of::<T>() > 0, "value size must be greather than zero");
+
+        let count = self.deps.len();
+        let size : usize = 1 << self.log2;
+
+        // ancestors[s][o]: stage o always passes a slot before stage s does
+        let mut ancestors = vec![vec![false; count]; count];
+        for stage in 0..count {
+            for &dep in &self.deps[stage] {
+                let inherited = ancestors[dep].clone();
+                for (mine, theirs) in ancestors[stage].iter_mut().zip(inherited) {
+                    *mine |= theirs;
+                }
+                ancestors[stage][dep] = true;
+            }
+        }
+
+        let stages = self.deps.into_iter()
+            .enumerate()
+            .map(|(stage, deps)| StageState {
+                cursor: Cursor::new(size as u32),
+                deps: deps.into_boxed_slice(),
+                exclusive: (0..count).all(|other| {
+                    other == stage || ancestors[stage][other] || ancestors[other][stage]
+                }),
+            })
+            .collect();
+
+        let shared = Arc::new(Shared {
+            prod: Cursor::new(size as u32),
+            stages,
+            inner: Buffer::new(size),
+            filled: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+        });
+
+        let handles = (0..count)
+            .map(|id| Stage { shared: Arc::clone(&shared), id })
+            .collect();
+
+        (Producer { shared }, handles)
+    }
+}
+
+// a stage cursor, its tail is the next position to process
+struct StageState {
+    cursor: Cursor,
+    deps: Box<[usize]>,
+    exclusive: bool,
+}
+
+struct Shared<T> {
+    prod: Cursor,
+    stages: Box<[StageState]>,
+    inner: Buffer<T>,
+    // slots below this index hold an item, positions start at zero so they
+    // fill up in order
+    filled: AtomicUsize,
+    closed: AtomicBool,
+}
+
+unsafe impl<T: Send + Sync> Send for Shared<T> {}
+unsafe impl<T: Send + Sync> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    // how far a stage may advance: the slowest of its dependencies, or the
+    // producer if it has none
+    #[inline]
+    fn barrier(&self, id: usize) -> u32 {
+        let deps = &self.stages[id].deps;
+        if deps.is_empty() {
+            return self.prod.back();
+        }
+
+        let pos = self.stages[id].cursor.back();
+        deps.iter()
+            .map(|&dep| self.stages[dep].cursor.back())
+            .min_by_key(|&tail| tail.wrapping_sub(pos))
+            .unwrap()
+    }
+
+    // how far the slowest stage is behind `head`
+    #[inline]
+    fn lag(&self, head: u32) -> u32 {
+        self.stages.iter()
+            .map(|stage| head.wrapping_sub(stage.cursor.back()))
+            .max()
+            .unwrap()
+    }
+
+    #[inline]
+    fn publish(&self, value: T) -> Option<T> {
+        let head = self.prod.front();
+        let next = self.prod.next(head);
+
+        if self.lag(head) == self.prod.size() {
+            return Some(value);
+        }
+
+        let index = self.prod.index(head);
+        if index < self.filled.load(Ordering::Relaxed) {
+            unsafe { self.inner.drop_at(index) };
+        } else {
+            self.filled.store(index + 1, Ordering::Relaxed);
+        }
+        self.inner.write(index, value);
+
+        self.prod.head.store(next, Ordering::Release);
+        self.prod.tail.store(next, Ordering::Release);
+
+        None
+    }
+
+    // hands every slot the stage may process to `f`, then moves its cursor
+    // past them in one go
+    #[inline]
+    fn process<F: FnMut(*mut T)>(&self, id: usize, mut f: F) -> usize {
+        let cursor = &self.stages[id].cursor;
+        let pos = cursor.back();
+        let end = self.barrier(id);
+        let count = end.wrapping_sub(pos);
+
+        let mut next = pos;
+        while next != end {
+            f(self.inner.as_mut_ptr(cursor.index(next)));
+            next = cursor.next(next);
+        }
+
+        cursor.tail.store(end, Ordering::Release);
+
+        count as usize
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        for index in 0..*self.filled.get_mut() {
+            unsafe { self.inner.drop_at(index) };
+        }
+    }
+}
+
+/// The publishing end of a pipeline.
+pub struct Producer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// One stage of a pipeline.
+pub struct Stage<T> {
+    shared: Arc<Shared<T>>,
+    id: usize,
+}
+
+impl<T> Producer<T> {
+    /// Publishes the item, or gives it back if the slowest stage is a full
+    /// lap behind.
+    #[inline]
+    pub fn try_publish(&mut self, value: T) -> Option<T> {
+        self.shared.publish(value)
+    }
+
+    /// Publishes the item, spinning while the slowest stage is a full lap
+    /// behind.
+    #[inline]
+    pub fn publish(&mut self, mut value: T) {
+        while let Some(v) = self.shared.publish(value) {
+            value = v;
+            spin_loop();
+        }
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.shared.prod.size() as usize
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+impl<T> Stage<T> {
+    #[inline]
+    pub fn id(&self) -> StageId {
+        StageId(self.id)
+    }
+
+    /// Whether the stage is ordered against every other stage and so may
+    /// mutate items in place.
+    #[inline]
+    pub fn is_exclusive(&self) -> bool {
+        self.shared.stages[self.id].exclusive
+    }
+
+    /// Number of slots the stage may process right now.
+    #[inline]
+    pub fn available(&self) -> usize {
+        let pos = self.shared.stages[self.id].cursor.back();
+        self.shared.barrier(self.id).wrapping_sub(pos) as usize
+    }
+
+    /// Whether the producer is gone and the stage processed every item it
+    /// published.
+    #[inline]
+    pub fn is_finished(&self) -> bool {
+        self.shared.closed.load(Ordering::SeqCst)
+            && self.shared.prod.reached(self.shared.stages[self.id].cursor.back())
+    }
+
+    /// Hands every available item to `f` in publishing order and returns
+    /// how many there were.
+    ///
+    /// # Panics
+    ///
+    /// If the stage is not exclusive, see `is_exclusive`.
+    #[inline]
+    pub fn try_process<F: FnMut(&mut T)>(&mut self, mut f: F) -> usize {
+        assert!(self.is_exclusive(), "stage may run alongside another stage, use try_inspect");
+        self.shared.process(self.id, |slot| f(unsafe { &mut *slot }))
+    }
+
+    /// Like `try_process`, but only with shared access, for stages that may
+    /// run alongside others on the same slot.
+    #[inline]
+    pub fn try_inspect<F: FnMut(&T)>(&mut self, mut f: F) -> usize {
+        self.shared.process(self.id, |slot| f(unsafe { &*slot }))
+    }
+
+    /// Processes items as they become available until `is_finished`.
+    ///
+    /// # Panics
+    ///
+    /// If the stage is not exclusive, see `is_exclusive`, even when there
+    /// is nothing left to process.
+    #[inline]
+    pub fn run<F: FnMut(&mut T)>(&mut self, mut f: F) {
+        assert!(self.is_exclusive(), "stage may run alongside another stage, use try_inspect");
+        while !self.is_finished() {
+            if self.try_process(&mut f) == 0 {
+                spin_loop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::*;
+    use crate::test_util::Drops;
+
+    #[test]
+    fn test_exclusive() {
+        let mut pipeline = Pipeline::new(2);
+        let a = pipeline.stage(&[]);
+        let b = pipeline.stage(&[a]);
+        let c = pipeline.stage(&[a]);
+        let d = pipeline.stage(&[b, c]);
+        let (_, stages) = pipeline.build::<u64>();
+
+        // b and c may work on the same slot at once
+        assert!(stages[a.index()].is_exclusive());
+        assert!(!stages[b.index()].is_exclusive());
+        assert!(!stages[c.index()].is_exclusive());
+        assert!(stages[d.index()].is_exclusive());
+    }
+
+    #[test]
+    fn test_barriers() {
+        let mut pipeline = Pipeline::new(2);
+        let first = pipeline.stage(&[]);
+        let second = pipeline.stage(&[first]);
+        let (mut producer, mut stages) = pipeline.build::<u64>();
+
+        for i in 0..4 {
+            assert_eq!(producer.try_publish(i), None);
+        }
+        // no stage has passed a slot yet
+        assert_eq!(producer.try_publish(4), Some(4));
+
+        // the second stage waits for the first
+        assert_eq!(stages[second.index()].available(), 0);
+        assert_eq!(stages[first.index()].try_process(|v| *v += 10), 4);
+        assert_eq!(producer.try_publish(4), Some(4));
+
+        let mut seen = Vec::new();
+        assert_eq!(stages[second.index()].try_process(|v| seen.push(*v)), 4);
+        assert_eq!(seen, [10, 11, 12, 13]);
+
+        assert_eq!(producer.try_publish(4), None);
+        assert_eq!(stages[second.index()].available(), 0);
+        assert_eq!(stages[first.index()].available(), 1);
+        assert!(!stages[first.index()].is_finished());
+
+        drop(producer);
+        stages[first.index()].try_process(|_| {});
+        assert!(stages[first.index()].is_finished());
+        assert!(!stages[second.index()].is_finished());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_process_shared_stage() {
+        let mut pipeline = Pipeline::new(2);
+        pipeline.stage(&[]);
+        pipeline.stage(&[]);
+        let (_, mut stages) = pipeline.build::<u64>();
+
+        stages[0].try_process(|_| {});
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_run_shared_stage() {
+        let mut pipeline = Pipeline::new(2);
+        pipeline.stage(&[]);
+        pipeline.stage(&[]);
+        let (producer, mut stages) = pipeline.build::<u64>();
+
+        // finished right away, the stage must be rejected all the same
+        drop(producer);
+        stages[0].run(|_| {});
+    }
+
+    #[test]
+    fn test_drop() {
+        let drops = Drops::new();
+        {
+            let mut pipeline = Pipeline::new(2);
+            pipeline.stage(&[]);
+            let (mut producer, mut stages) = pipeline.build();
+
+            for _ in 0..6 {
+                producer.publish(drops.item());
+                stages[0].try_process(|_| {});
+            }
+            // two slots reused so far
+            assert_eq!(drops.count(), 2);
+        }
+        assert_eq!(drops.count(), 6);
+    }
+
+    #[test]
+    fn test_pipeline() {
+        const N : u64 = 100_000;
+
+        let mut pipeline = Pipeline::new(4);
+        let decode = pipeline.stage(&[]);
+        let left = pipeline.stage(&[decode]);
+        let right = pipeline.stage(&[decode]);
+        let persist = pipeline.stage(&[left, right]);
+        let (mut producer, stages) = pipeline.build::<(u64, u64)>();
+
+        let handles = stages.into_iter()
+            .map(|mut stage| {
+                let id = stage.id();
+                thread::spawn(move || {
+                    let mut sum = 0;
+                    while !stage.is_finished() {
+                        let count = if id == decode {
+                            stage.try_process(|v| v.1 = v.0 * 2)
+                        } else if id == persist {
+                            stage.try_process(|v| {
+                                assert_eq!(v.1, v.0 * 2);
+                                sum += v.1;
+                            })
+                        } else {
+                            stage.try_inspect(|v| sum += v.1)
+                        };
+                        if count == 0 {
+                            thread::yield_now();
+                        }
+                    }
+                    sum
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for i in 0..N {
+            let mut value = (i, 0);
+            while let Some(v) = producer.try_publish(value) {
+                value = v;
+                thread::yield_now();
+            }
+        }
+        drop(producer);
+
+        let sums = handles.into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(sums, [0, N * (N - 1), N * (N - 1), N * (N - 1)]);
+    }
+}
@@ -0,0 +1,409 @@
+//! Lock-free MPMC ring where every slot carries its own sequence stamp.
+//!
+//! `Ring` hands slots over through the cursor tails, which only move in
+//! order: a producer preempted between reserving its slot and publishing it
+//! holds up every producer that reserved after it, and consumers likewise.
+//! A `StampedRing` only shares the heads, each slot then tells by its stamp
+//! whether it is free for the producer of a position or holds the item of a
+//! consumer's position, so every operation completes on its own.
+//!
+//! The price is a stamp per slot and an item that is still being written
+//! reads as not there yet: a consumer can find the ring empty while later
+//! positions are already published, and a producer can find it full while
+//! earlier positions are already consumed.
+
+use core::cmp;
+use core::fmt::{self, Debug};
+use core::marker::PhantomData;
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::boxed::Box;
+
+use crate::buffer::{Buffer, Storage};
+use crate::cursor::Cursor;
+use crate::sync::AtomicU32;
+
+/// Lock-free MPMC ring of `2^log2` slots, handing slots over with per slot
+/// stamps instead of cursor tails.
+///
+/// The stamp of a slot is the position it is ready for: `pos` while free
+/// for the producer of `pos`, `pos + 1` once that producer wrote its item,
+/// and `pos + size` once the consumer took it, which frees the slot for the
+/// next lap. A release store of the stamp hands the slot over, the other
+/// side acquires it before touching the slot.
+///
+/// ```
+/// use ring::stamped::StampedRing;
+///
+/// let ring = StampedRing::new(2);
+/// assert_eq!(ring.multi_enqueue(1), None);
+/// assert_eq!(ring.multi_dequeue(), Some(1));
+/// assert_eq!(ring.multi_dequeue(), None);
+/// ```
+pub struct StampedRing<T, S: Storage<T> = Buffer<T>> {
+    // only the heads are used, slots are handed over by their stamps
+    prod: Cursor,
+    cons: Cursor,
+    stamps: Box<[AtomicU32]>,
+    inner: S,
+    overwritten: AtomicUsize,
+    _marker: PhantomData<T>,
+}
+
+impl<T> StampedRing<T> {
+    #[inline]
+    pub fn new(log2 : usize) -> Self {
+        assert!(log2 > 1, "log2 must give greather than 1");
+        assert!(log2 < 32, "log2 must give less than 32");
+        assert!(mem::size_of::<T>() > 0, "value size must be greather than zero");
+
+        let size : usize = 1 << log2;
+
+        StampedRing{
+            prod: Cursor::new(size as u32),
+            cons: Cursor::new(size as u32),
+            stamps: (0..size as u32).map(AtomicU32::new).collect(),
+            inner: Buffer::new(size),
+            overwritten: AtomicUsize::new(0),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, S: Storage<T>> StampedRing<T, S> {
+    /// Number of claimed positions not yet claimed by a consumer, items
+    /// still being written included.
+    #[inline]
+    pub fn len(&self) -> usize {
+        let head = self.cons.front();
+        let tail = self.prod.front();
+        cmp::min(tail.wrapping_sub(head), self.prod.size()) as usize
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    /// Whether an enqueue would find a free slot: the stamp of the next
+    /// one says its consumer is done with it.
+    #[inline]
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn has_free_slot(&self) -> bool {
+        let head = self.prod.front();
+        let stamp = self.stamps[self.prod.index(head)].load(Ordering::Acquire);
+        stamp.wrapping_sub(head) as i32 >= 0
+    }
+
+    /// Whether a dequeue would find an item: the stamp of the next slot
+    /// says its producer is done with it.
+    #[inline]
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn has_item(&self) -> bool {
+        let head = self.cons.front();
+        let stamp = self.stamps[self.cons.index(head)].load(Ordering::Acquire);
+        stamp.wrapping_sub(head.wrapping_add(1)) as i32 >= 0
+    }
+
+    // claims the next position and never writes it, like a producer
+    // preempted half way through; only for items that need no drop
+    #[cfg(test)]
+    pub(crate) fn stall_enqueue(&self) {
+        let head = self.prod.front();
+        assert!(self.prod.exchange_front(head, self.prod.next(head)));
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.prod.size() as usize
+    }
+
+    /// Number of free slots, `capacity() - len()`.
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        self.capacity() - self.len()
+    }
+
+    /// Number of items evicted by `force_enqueue` so far.
+    #[inline]
+    pub fn overwritten(&self) -> usize {
+        self.overwritten.load(Ordering::Relaxed)
+    }
+
+    /// Enqueues even when the ring is full by evicting the oldest item,
+    /// which is returned, so the producer never has to wait.
+    ///
+    /// Eviction dequeues on the producer's thread, so concurrent consumers
+    /// must use `multi_dequeue`.
+    #[inline]
+    pub fn force_enqueue(&self, value: T) -> Option<T> {
+        let mut value = value;
+        let mut evicted = None;
+        loop {
+            match self.multi_enqueue(value) {
+                None => return evicted,
+                Some(moved) => value = moved,
+            }
+            // a slot still being written or read is neither free nor
+            // evictable, both are tried again until it is done with
+            if let Some(oldest) = self.multi_dequeue() {
+                self.overwritten.fetch_add(1, Ordering::Relaxed);
+                evicted = Some(oldest);
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The caller must guarantee that no other thread enqueues into the
+    /// ring concurrently.
+    #[inline]
+    pub unsafe fn single_enqueue(&self, value: T) -> Option<T> {
+        // nobody else moves the head, the slot only has to be free
+        let head = self.prod.front();
+        let index = self.prod.index(head);
+        if self.stamps[index].load(Ordering::Acquire) != head {
+            return Some(value);
+        }
+        self.prod.head.store(self.prod.next(head), Ordering::Release);
+
+        self.inner.write(index, value);
+        self.stamps[index].store(head.wrapping_add(1), Ordering::Release);
+
+        None
+    }
+
+    /// # Safety
+    ///
+    /// The caller must guarantee that no other thread dequeues from the
+    /// ring concurrently.
+    #[inline]
+    pub unsafe fn single_dequeue(&self) -> Option<T> {
+        let head = self.cons.front();
+        let index = self.cons.index(head);
+        if self.stamps[index].load(Ordering::Acquire) != head.wrapping_add(1) {
+            return None;
+        }
+        self.cons.head.store(self.cons.next(head), Ordering::Release);
+
+        let value = unsafe { self.inner.read(index) };
+        self.stamps[index].store(head.wrapping_add(self.cons.size()), Ordering::Release);
+
+        Some(value)
+    }
+
+    #[inline]
+    pub fn multi_enqueue(&self, value: T) -> Option<T> {
+        let mut head = self.prod.front();
+        let index = loop {
+            let index = self.prod.index(head);
+            // how far the slot is from being free for `head`, positions
+            // are never more than a lap apart so the difference fits
+            let diff = self.stamps[index].load(Ordering::Acquire).wrapping_sub(head) as i32;
+            if diff < 0 {
+                // still holds the item of the previous lap
+                return Some(value);
+            }
+            if diff == 0 && self.prod.exchange_front(head, self.prod.next(head)) {
+                break index;
+            }
+            head = self.prod.front();
+        };
+
+        self.inner.write(index, value);
+        self.stamps[index].store(head.wrapping_add(1), Ordering::Release);
+
+        None
+    }
+
+    #[inline]
+    pub fn multi_dequeue(&self) -> Option<T> {
+        let mut head = self.cons.front();
+        let index = loop {
+            let index = self.cons.index(head);
+            let diff = self.stamps[index].load(Ordering::Acquire).wrapping_sub(head.wrapping_add(1)) as i32;
+            if diff < 0 {
+                // not written yet for this lap
+                return None;
+            }
+            if diff == 0 && self.cons.exchange_front(head, self.cons.next(head)) {
+                break index;
+            }
+            head = self.cons.front();
+        };
+
+        let value = unsafe { self.inner.read(index) };
+        self.stamps[index].store(head.wrapping_add(self.cons.size()), Ordering::Release);
+
+        Some(value)
+    }
+}
+
+impl<T, S: Storage<T>> Debug for StampedRing<T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StampedRing")
+            .field("prod", &self.prod.front())
+            .field("cons", &self.cons.front())
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+impl<T, S: Storage<T>> Drop for StampedRing<T, S> {
+    fn drop(&mut self) {
+        // with `&mut self` nothing is half way through: every claimed
+        // position not yet consumed holds its item
+        let mut pos = self.cons.front();
+        let head = self.prod.front();
+        while pos != head {
+            unsafe { self.inner.drop_at(self.cons.index(pos)) };
+            pos = pos.wrapping_add(1);
+        }
+    }
+}
+
+// items are moved between threads, never shared: `T: Send` is enough
+unsafe impl<T: Send, S: Storage<T> + Send> Sync for StampedRing<T, S> {}
+unsafe impl<T: Send, S: Storage<T> + Send> Send for StampedRing<T, S> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{Counted, Drops};
+
+    #[test]
+    fn test_constructor() {
+        let ring : StampedRing<u8> = StampedRing::new(3);
+        assert_eq!(ring.capacity(), 8);
+        assert!(ring.is_empty());
+        assert_eq!(ring.multi_dequeue(), None);
+    }
+
+    #[test]
+    fn test_multi() {
+        let ring : StampedRing<u8> = StampedRing::new(2);
+
+        for i in 0..4 {
+            assert_eq!(ring.multi_enqueue(i), None);
+        }
+        assert_eq!(ring.multi_enqueue(4), Some(4));
+        assert!(ring.is_full());
+
+        for i in 0..4 {
+            assert_eq!(ring.multi_dequeue(), Some(i));
+        }
+        assert_eq!(ring.multi_dequeue(), None);
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn test_single() {
+        let ring : StampedRing<u8> = StampedRing::new(2);
+
+        for round in 0..3 {
+            for i in 0..4 {
+                assert_eq!(unsafe { ring.single_enqueue(round * 4 + i) }, None);
+            }
+            assert_eq!(unsafe { ring.single_enqueue(0) }, Some(0));
+            assert!(!ring.has_free_slot());
+
+            // both paths hand slots over the same way
+            assert_eq!(ring.multi_dequeue(), Some(round * 4));
+            assert!(ring.has_free_slot());
+            for i in 1..4 {
+                assert_eq!(unsafe { ring.single_dequeue() }, Some(round * 4 + i));
+            }
+            assert_eq!(unsafe { ring.single_dequeue() }, None);
+        }
+    }
+
+    #[test]
+    fn test_has_item() {
+        let ring : StampedRing<u8> = StampedRing::new(2);
+        assert!(!ring.has_item());
+
+        ring.stall_enqueue();
+        assert!(!ring.is_empty());
+        assert!(!ring.has_item());
+        assert_eq!(ring.multi_dequeue(), None);
+
+        // a later producer completes, the stalled slot still comes first
+        assert_eq!(ring.multi_enqueue(1), None);
+        assert!(!ring.has_item());
+    }
+
+    #[test]
+    fn test_force_enqueue() {
+        let ring : StampedRing<u8> = StampedRing::new(2);
+
+        for i in 0..4 {
+            assert_eq!(ring.force_enqueue(i), None);
+        }
+        assert_eq!(ring.force_enqueue(4), Some(0));
+        assert_eq!(ring.force_enqueue(5), Some(1));
+        assert_eq!(ring.overwritten(), 2);
+        assert_eq!(ring.remaining(), 0);
+
+        for i in 2..6 {
+            assert_eq!(ring.multi_dequeue(), Some(i));
+        }
+    }
+
+    #[test]
+    fn test_wrap() {
+        let ring : StampedRing<u32> = StampedRing::new(2);
+        let start = u32::MAX - 1;
+        ring.prod.head.store(start, Ordering::Release);
+        ring.cons.head.store(start, Ordering::Release);
+        for pos in (0..4).map(|i| start.wrapping_add(i)) {
+            ring.stamps[ring.prod.index(pos)].store(pos, Ordering::Release);
+        }
+
+        for round in 0..4 {
+            for i in 0..4 {
+                assert_eq!(ring.multi_enqueue(round * 4 + i), None);
+            }
+            assert_eq!(ring.multi_enqueue(0), Some(0));
+            assert_eq!(ring.len(), 4);
+
+            for i in 0..4 {
+                assert_eq!(ring.multi_dequeue(), Some(round * 4 + i));
+            }
+            assert_eq!(ring.multi_dequeue(), None);
+        }
+    }
+
+    #[test]
+    fn test_drop() {
+        let drops = Drops::new();
+
+        let ring : StampedRing<Counted> = StampedRing::new(2);
+        for _ in 0..6 {
+            ring.multi_enqueue(drops.item());
+            drop(ring.multi_dequeue());
+        }
+        assert_eq!(drops.count(), 6);
+
+        for _ in 0..3 {
+            assert!(ring.multi_enqueue(drops.item()).is_none());
+        }
+        drop(ring);
+        assert_eq!(drops.count(), 9);
+    }
+
+    #[test]
+    fn test_debug() {
+        let ring : StampedRing<u8> = StampedRing::new(2);
+        ring.multi_enqueue(0);
+        assert_eq!(
+            format!("{:?}", ring),
+            "StampedRing { prod: 1, cons: 0, len: 1, capacity: 4 }"
+        );
+    }
+}
@@ -0,0 +1,29 @@
+// The atomics the cursor protocol is built on. Test builds swap in the
+// instrumented versions of the model checker, which behave like the real
+// ones outside of a model run. Builds with `--cfg loom` swap in loom's, for
+// the same model tests to run under its weak memory model instead.
+
+#[cfg(not(any(test, loom)))]
+pub(crate) use core::sync::atomic::AtomicU32;
+#[cfg(not(any(test, loom)))]
+pub(crate) use core::hint::spin_loop;
+
+#[cfg(all(test, not(loom)))]
+pub(crate) use crate::model::{AtomicU32, spin_loop};
+
+#[cfg(loom)]
+pub(crate) use loom::hint::spin_loop;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::AtomicU32;
+
+// A `const fn`, except under loom: its atomics can only be built within a
+// model run, so nothing holding one can be built in a `const`.
+macro_rules! const_fn {
+    ($(#[$attr:meta])* $vis:vis fn $($fn:tt)*) => {
+        #[cfg(not(loom))]
+        $(#[$attr])* $vis const fn $($fn)*
+
+        #[cfg(loom)]
+        $(#[$attr])* $vis fn $($fn)*
+    };
+}
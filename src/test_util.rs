@@ -0,0 +1,32 @@
+// Fixtures shared by the unit tests of the ring types.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// an item that counts its drops, clones count into the same counter
+#[derive(Debug, Clone)]
+pub(crate) struct Counted(Arc<AtomicUsize>);
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+// hands out `Counted` items and tells how many of them were dropped
+#[derive(Debug, Default)]
+pub(crate) struct Drops(Arc<AtomicUsize>);
+
+impl Drops {
+    pub fn new() -> Self {
+        Drops::default()
+    }
+
+    pub fn item(&self) -> Counted {
+        Counted(Arc::clone(&self.0))
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,524 @@
+use std::cell::RefCell;
+use std::fmt::{self, Debug};
+use std::hint::spin_loop;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::buffer::{Buffer, Storage};
+use crate::cursor::Cursor;
+use crate::error::{SendError, RecvError, TryRecvError, RecvTimeoutError};
+use crate::wait::{WaitStrategy, BusySpin};
+
+// drained segments kept around for reuse instead of being freed
+const SPARES: usize = 2;
+
+// A fixed run of slots, each one written and read exactly once: positions
+// go from zero up to the size and never wrap, so once a producer finds the
+// segment exhausted no later item can land in it and order is kept across
+// segments. The channel holds the head segment and through the links every
+// one after it up to the tail. Each handle holds the segment it works on,
+// the link out of a segment is cut once the head moves past it, so an idle
+// handle keeps that one segment alive and nothing after it.
+struct Segment<T> {
+    prod: Cursor,
+    cons: Cursor,
+    inner: Buffer<T>,
+    // only touched when a handle crosses over to the next segment
+    next: Mutex<Option<Arc<Segment<T>>>>,
+}
+
+enum Pop<T> {
+    Item(T),
+    Empty,
+    Exhausted,
+}
+
+unsafe impl<T: Send> Send for Segment<T> {}
+unsafe impl<T: Send> Sync for Segment<T> {}
+
+impl<T> Segment<T> {
+    fn new(size: usize) -> Self {
+        Segment {
+            prod: Cursor::new(size as u32),
+            cons: Cursor::new(size as u32),
+            inner: Buffer::new(size),
+            next: Mutex::new(None),
+        }
+    }
+
+    // gives the value back once every slot has been handed out
+    #[inline]
+    fn push(&self, value: T) -> Option<T> {
+        let head = loop {
+            let head = self.prod.front();
+            if head == self.prod.size() {
+                return Some(value);
+            }
+            if self.prod.exchange_front(head, head + 1) {
+                break head;
+            }
+            spin_loop();
+        };
+
+        self.inner.write(self.prod.index(head), value);
+
+        while !self.prod.exchange_back(head, head + 1) {
+            spin_loop();
+        }
+
+        None
+    }
+
+    #[inline]
+    fn pop(&self) -> Pop<T> {
+        let head = loop {
+            let head = self.cons.front();
+            if head == self.cons.size() {
+                return Pop::Exhausted;
+            }
+            if self.prod.reached(head) {
+                return Pop::Empty;
+            }
+            if self.cons.exchange_front(head, head + 1) {
+                break head;
+            }
+            spin_loop();
+        };
+
+        let value = unsafe { self.inner.read(self.cons.index(head)) };
+
+        while !self.cons.exchange_back(head, head + 1) {
+            spin_loop();
+        }
+
+        Pop::Item(value)
+    }
+}
+
+impl<T> Drop for Segment<T> {
+    fn drop(&mut self) {
+        let tail = self.prod.back();
+        let mut pos = self.cons.back();
+        while pos != tail {
+            unsafe { self.inner.drop_at(self.cons.index(pos)) };
+            pos += 1;
+        }
+
+        // unlink the rest of the chain one segment at a time, dropping it
+        // recursively could run out of stack on a long backlog
+        let mut next = self.next.get_mut().unwrap().take();
+        while let Some(segment) = next {
+            next = match Arc::try_unwrap(segment) {
+                Ok(mut segment) => segment.next.get_mut().unwrap().take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+struct Shared<T, W> {
+    size: usize,
+    // the segment receivers drain and the one senders fill, only locked by
+    // a handle moving on to the next segment
+    head: Mutex<Arc<Segment<T>>>,
+    tail: Mutex<Arc<Segment<T>>>,
+    spares: Mutex<Vec<Arc<Segment<T>>>>,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    // receivers wait here while the channel is empty
+    not_empty: W,
+}
+
+impl<T, W> Shared<T, W> {
+    fn segment(&self) -> Arc<Segment<T>> {
+        match self.spares.lock().unwrap().pop() {
+            Some(segment) => segment,
+            None => Arc::new(Segment::new(self.size)),
+        }
+    }
+
+    // the segment after the full `full`, linked by the first sender that
+    // finds it full
+    #[cold]
+    fn advance_tail(&self, full: &Arc<Segment<T>>) -> Arc<Segment<T>> {
+        let mut tail = self.tail.lock().unwrap();
+        if Arc::ptr_eq(&tail, full) {
+            let next = self.segment();
+            *full.next.lock().unwrap() = Some(Arc::clone(&next));
+            *tail = next;
+        }
+        Arc::clone(&tail)
+    }
+
+    // the segment after the drained `drained`, `None` while no sender
+    // linked one yet
+    #[cold]
+    fn advance_head(&self, drained: &Arc<Segment<T>>) -> Option<Arc<Segment<T>>> {
+        let mut head = self.head.lock().unwrap();
+        if Arc::ptr_eq(&head, drained) {
+            // from here on the head holds the rest of the chain
+            *head = drained.next.lock().unwrap().take()?;
+        }
+        Some(Arc::clone(&head))
+    }
+
+    // a handle moved on from `segment`; once the last one did, nobody can
+    // reach it any more and it is reset and kept
+    fn retire(&self, mut segment: Arc<Segment<T>>) {
+        if let Some(inner) = Arc::get_mut(&mut segment) {
+            // every slot was read, so there is nothing left to drop
+            for cursor in [&mut inner.prod, &mut inner.cons] {
+                *cursor = Cursor::new(cursor.size());
+            }
+            inner.next.get_mut().unwrap().take();
+
+            let mut spares = self.spares.lock().unwrap();
+            if spares.len() < SPARES {
+                spares.push(segment);
+            }
+        }
+    }
+
+    fn fmt_handle(&self, name: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct(name)
+            .field("segment_size", &self.size)
+            .field("senders", &self.senders.load(Ordering::Relaxed))
+            .field("receivers", &self.receivers.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+/// Sending end of an unbounded channel, `send` never waits.
+///
+/// Each handle keeps the segment it sends to, so it can be sent to another
+/// thread but not shared: clone it instead.
+pub struct UnboundedSender<T, W: WaitStrategy = BusySpin> {
+    shared: Arc<Shared<T, W>>,
+    segment: RefCell<Arc<Segment<T>>>,
+}
+
+/// Receiving end of an unbounded channel, like the sender it keeps the
+/// segment it receives from.
+pub struct UnboundedReceiver<T, W: WaitStrategy = BusySpin> {
+    shared: Arc<Shared<T, W>>,
+    segment: RefCell<Arc<Segment<T>>>,
+}
+
+impl<T, W: WaitStrategy> UnboundedSender<T, W> {
+    pub fn send(&self, v: T) -> Result<(), SendError<T>> {
+        if self.shared.receivers.load(Ordering::Acquire) == 0 {
+            return Err(SendError(v));
+        }
+
+        let mut value = v;
+        loop {
+            match self.segment.borrow().push(value) {
+                None => break,
+                Some(moved) => value = moved,
+            }
+            let next = self.shared.advance_tail(&self.segment.borrow());
+            let full = self.segment.replace(next);
+            self.shared.retire(full);
+        }
+
+        self.shared.not_empty.notify();
+        Ok(())
+    }
+}
+
+impl<T, W: WaitStrategy> UnboundedReceiver<T, W> {
+    #[inline]
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.recv_until(None).map_err(|_| RecvError)
+    }
+
+    #[inline]
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.recv_until(Some(deadline)),
+            None => self.recv_until(None),
+        }
+    }
+
+    #[inline]
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(deadline))
+    }
+
+    fn recv_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let mut attempt = 0;
+        loop {
+            let token = self.shared.not_empty.prepare();
+            match self.try_recv() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Empty) => (),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+            }
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    return Err(RecvTimeoutError::Timeout);
+                }
+            }
+            self.shared.not_empty.wait(token, attempt, deadline);
+            attempt = attempt.saturating_add(1);
+        }
+    }
+
+    #[inline]
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        if let Some(value) = self.dequeue() {
+            return Ok(value);
+        }
+        if self.shared.senders.load(Ordering::Acquire) == 0 {
+            return self.dequeue().ok_or(TryRecvError::Disconnected);
+        }
+        Err(TryRecvError::Empty)
+    }
+
+    #[inline]
+    fn dequeue(&self) -> Option<T> {
+        loop {
+            match self.segment.borrow().pop() {
+                Pop::Item(value) => return Some(value),
+                Pop::Empty => return None,
+                Pop::Exhausted => (),
+            }
+            let next = self.shared.advance_head(&self.segment.borrow())?;
+            let drained = self.segment.replace(next);
+            self.shared.retire(drained);
+        }
+    }
+}
+
+impl<T, W: WaitStrategy> Clone for UnboundedSender<T, W> {
+    #[inline]
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::AcqRel);
+        let segment = RefCell::new(Arc::clone(&self.segment.borrow()));
+        UnboundedSender { shared: Arc::clone(&self.shared), segment }
+    }
+}
+
+impl<T, W: WaitStrategy> Clone for UnboundedReceiver<T, W> {
+    #[inline]
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::AcqRel);
+        let segment = RefCell::new(Arc::clone(&self.segment.borrow()));
+        UnboundedReceiver { shared: Arc::clone(&self.shared), segment }
+    }
+}
+
+impl<T, W: WaitStrategy> Debug for UnboundedSender<T, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.shared.fmt_handle("UnboundedSender", f)
+    }
+}
+
+impl<T, W: WaitStrategy> Debug for UnboundedReceiver<T, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.shared.fmt_handle("UnboundedReceiver", f)
+    }
+}
+
+impl<T, W: WaitStrategy> Drop for UnboundedSender<T, W> {
+    #[inline]
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.not_empty.notify();
+        }
+    }
+}
+
+impl<T, W: WaitStrategy> Drop for UnboundedReceiver<T, W> {
+    #[inline]
+    fn drop(&mut self) {
+        self.shared.receivers.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Unbounded channel made of linked segments of `2^log2` slots: a full
+/// segment gets a new one linked after it, drained segments are recycled.
+pub fn unbounded<T>(log2: usize) -> (UnboundedSender<T>, UnboundedReceiver<T>) {
+    unbounded_with(log2, BusySpin)
+}
+
+/// Like `unbounded`, blocking receives wait with `wait`.
+pub fn unbounded_with<T, W: WaitStrategy>(log2: usize, wait: W)
+    -> (UnboundedSender<T, W>, UnboundedReceiver<T, W>) {
+    assert!(log2 > 1, "log2 must give greather than 1");
+    assert!(log2 < 32, "log2 must give less than 32");
+    assert!(mem::size_of::<T>() > 0, "value size must be greather than zero");
+
+    let size : usize = 1 << log2;
+    let first = Arc::new(Segment::new(size));
+
+    let shared = Arc::new(Shared {
+        size,
+        head: Mutex::new(Arc::clone(&first)),
+        tail: Mutex::new(Arc::clone(&first)),
+        spares: Mutex::new(Vec::new()),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+        not_empty: wait,
+    });
+
+    let sender = UnboundedSender { shared: Arc::clone(&shared), segment: RefCell::new(Arc::clone(&first)) };
+    let receiver = UnboundedReceiver { shared, segment: RefCell::new(first) };
+    (sender, receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use crate::wait::Park;
+    use super::*;
+    use crate::test_util::Drops;
+
+    #[test]
+    fn test_fifo() {
+        let (tx, rx) = unbounded::<u32>(2);
+
+        // never full, segments are linked as needed
+        for i in 0..100 {
+            tx.send(i).unwrap();
+        }
+        for i in 0..100 {
+            assert_eq!(rx.try_recv(), Ok(i));
+        }
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+
+        drop(tx);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_recycle() {
+        let (tx, rx) = unbounded::<u32>(2);
+
+        for i in 0..8 {
+            tx.send(i).unwrap();
+        }
+        for i in 0..8 {
+            assert_eq!(rx.recv(), Ok(i));
+        }
+        // the first segment is drained and unreachable
+        assert_eq!(rx.shared.spares.lock().unwrap().len(), 1);
+
+        // and is linked again once the second one is full
+        tx.send(8).unwrap();
+        assert_eq!(rx.shared.spares.lock().unwrap().len(), 0);
+        assert_eq!(rx.recv(), Ok(8));
+        assert_eq!(rx.shared.spares.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_idle_handles() {
+        let (tx, rx) = unbounded::<u32>(2);
+        let idle = (tx.clone(), rx.clone());
+
+        for i in 0..16 {
+            tx.send(i).unwrap();
+        }
+        for i in 0..16 {
+            assert_eq!(rx.recv(), Ok(i));
+        }
+        // the idle clones keep the first segment, but none of the drained
+        // ones after it
+        assert_eq!(Arc::strong_count(&idle.0.segment.borrow()), 2);
+        assert!(idle.0.segment.borrow().next.lock().unwrap().is_none());
+        assert_eq!(rx.shared.spares.lock().unwrap().len(), 2);
+
+        // and catch up on their next operation, giving the first one back
+        let (tx1, rx1) = idle;
+        thread::spawn(move || tx1.send(16).unwrap()).join().unwrap();
+        assert_eq!(rx1.recv(), Ok(16));
+        assert_eq!(rx.shared.spares.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_debug() {
+        let (tx, rx) = unbounded::<u32>(2);
+        assert_eq!(
+            format!("{:?}", tx),
+            "UnboundedSender { segment_size: 4, senders: 1, receivers: 1 }"
+        );
+        assert!(format!("{:?}", rx).starts_with("UnboundedReceiver {"));
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (tx, rx) = unbounded::<u32>(2);
+        tx.send(0).unwrap();
+        drop(tx);
+
+        assert_eq!(rx.recv(), Ok(0));
+        assert_eq!(rx.recv(), Err(RecvError));
+
+        let (tx, rx) = unbounded::<u32>(2);
+        drop(rx);
+        assert_eq!(tx.send(0), Err(SendError(0)));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let (_tx, rx) = unbounded_with::<u32, _>(2, Park::new());
+        assert_eq!(rx.recv_timeout(Duration::from_millis(10)), Err(RecvTimeoutError::Timeout));
+    }
+
+    #[test]
+    fn test_drop() {
+        let drops = Drops::new();
+        {
+            let (tx, rx) = unbounded(2);
+            for _ in 0..10 {
+                tx.send(drops.item()).unwrap();
+            }
+            drop(rx.recv().unwrap());
+            assert_eq!(drops.count(), 1);
+        }
+        assert_eq!(drops.count(), 10);
+    }
+
+    #[test]
+    fn test_long_chain_drop() {
+        let (tx, rx) = unbounded::<u64>(2);
+        for i in 0..1_000_000 {
+            tx.send(i).unwrap();
+        }
+        drop((tx, rx));
+    }
+
+    #[test]
+    fn test_mpmc() {
+        const N : u64 = 100_000;
+
+        let (tx, rx) = unbounded_with::<u64, _>(4, Park::new());
+        let producers = (0..2)
+            .map(|_| {
+                let tx = tx.clone();
+                thread::spawn(move || (0..N).for_each(|i| tx.send(i).unwrap()))
+            })
+            .collect::<Vec<_>>();
+        drop(tx);
+
+        let consumers = (0..2)
+            .map(|_| {
+                let rx = rx.clone();
+                thread::spawn(move || {
+                    let mut sum = 0;
+                    while let Ok(value) = rx.recv() {
+                        sum += value;
+                    }
+                    sum
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(rx);
+
+        producers.into_iter().for_each(|handle| handle.join().unwrap());
+        let sum : u64 = consumers.into_iter().map(|handle| handle.join().unwrap()).sum();
+        assert_eq!(sum, N * (N - 1));
+    }
+}
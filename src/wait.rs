@@ -0,0 +1,357 @@
+use std::hint::spin_loop;
+use std::mem;
+use std::sync::Mutex;
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+use std::task::Waker;
+use std::thread::{self, Thread};
+use std::time::Instant;
+
+/// How a blocked `send` or `recv` waits for the other side of the channel.
+///
+/// A channel owns two instances: one its senders wait on while the ring is
+/// full, one its receivers wait on while the ring is empty. Each failed
+/// attempt is bracketed by `prepare` and `wait`, and every bit of progress
+/// on the opposite side calls `notify`.
+pub trait WaitStrategy: Send + Sync {
+    /// Called before an attempt; the token is handed back to `wait`.
+    #[inline]
+    fn prepare(&self) -> usize {
+        0
+    }
+
+    /// Called after `attempt` consecutive failed attempts. Must not block
+    /// past `deadline`, the caller checks for expiry on return.
+    fn wait(&self, token: usize, attempt: u32, deadline: Option<Instant>);
+
+    /// Called after the opposite side made progress or disconnected.
+    #[inline]
+    fn notify(&self) {}
+}
+
+/// Spins on the CPU, lowest latency at the cost of a busy core.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BusySpin;
+
+impl WaitStrategy for BusySpin {
+    #[inline]
+    fn wait(&self, _token: usize, _attempt: u32, _deadline: Option<Instant>) {
+        spin_loop();
+    }
+}
+
+/// Gives the rest of the time slice back to the scheduler.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Yield;
+
+impl WaitStrategy for Yield {
+    #[inline]
+    fn wait(&self, _token: usize, _attempt: u32, _deadline: Option<Instant>) {
+        thread::yield_now();
+    }
+}
+
+/// Spins for an exponentially growing number of iterations, then yields.
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    spin_limit: u32,
+}
+
+impl Backoff {
+    #[inline]
+    pub fn new(spin_limit: u32) -> Self {
+        Backoff { spin_limit }
+    }
+}
+
+impl Default for Backoff {
+    #[inline]
+    fn default() -> Self {
+        Backoff::new(6)
+    }
+}
+
+impl WaitStrategy for Backoff {
+    #[inline]
+    fn wait(&self, _token: usize, attempt: u32, _deadline: Option<Instant>) {
+        if attempt < self.spin_limit {
+            for _ in 0..1u32 << attempt {
+                spin_loop();
+            }
+        } else {
+            thread::yield_now();
+        }
+    }
+}
+
+/// Parks the blocked thread until the other side makes progress.
+///
+/// `notify` bumps an epoch, `wait` only parks if the epoch is still the one
+/// observed by `prepare`, so a wakeup between a failed attempt and the
+/// park is never lost.
+#[derive(Debug, Default)]
+pub struct Park {
+    epoch: AtomicUsize,
+    sleepers: AtomicUsize,
+    threads: Mutex<Vec<Thread>>,
+}
+
+impl Park {
+    #[inline]
+    pub fn new() -> Self {
+        Park::default()
+    }
+}
+
+impl Clone for Park {
+    #[inline]
+    fn clone(&self) -> Self {
+        Park::new()
+    }
+}
+
+impl WaitStrategy for Park {
+    #[inline]
+    fn prepare(&self) -> usize {
+        self.epoch.load(Ordering::SeqCst)
+    }
+
+    fn wait(&self, token: usize, _attempt: u32, deadline: Option<Instant>) {
+        self.sleepers.fetch_add(1, Ordering::SeqCst);
+        {
+            let mut threads = self.threads.lock().unwrap();
+            if self.epoch.load(Ordering::SeqCst) != token {
+                drop(threads);
+                self.sleepers.fetch_sub(1, Ordering::SeqCst);
+                return;
+            }
+            threads.push(thread::current());
+        }
+        match deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+                if now < deadline {
+                    thread::park_timeout(deadline - now);
+                }
+            },
+            None => thread::park(),
+        }
+        // a timeout or a spurious wakeup leaves the handle behind, it would
+        // pile up until the next notify that sees a sleeper
+        let id = thread::current().id();
+        self.threads.lock().unwrap().retain(|thread| thread.id() != id);
+        self.sleepers.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    #[inline]
+    fn notify(&self) {
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            let threads = mem::take(&mut *self.threads.lock().unwrap());
+            for thread in threads {
+                thread.unpark();
+            }
+        }
+    }
+}
+
+/// Wakers of the async tasks blocked on one side of a channel.
+///
+/// Same handshake as `Park`: a task registers, then tries again before it
+/// returns `Pending`, while `wake` only looks at the registered count after
+/// the progress it reports, so one of the two always sees the other.
+///
+/// Both sides store and then load a different location, the count on one
+/// and a ring cursor on the other, so both stores and loads have to be in
+/// a single total order. A task registering orders its side with a `SeqCst`
+/// fence. `wake` relies on the progress being a `SeqCst` read-modify-write,
+/// `wake_fenced` follows weaker progress with a fence of its own.
+///
+/// A future registers under a key of its own instead, so that dropping it
+/// removes its entry and not that of another future of the same task.
+#[derive(Debug, Default)]
+pub(crate) struct Wakers {
+    len: AtomicUsize,
+    wakers: Mutex<Registry>,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    entries: Vec<(Option<usize>, Waker)>,
+    keys: usize,
+}
+
+impl Wakers {
+    pub fn register(&self, waker: &Waker) {
+        {
+            let mut wakers = self.wakers.lock().unwrap();
+            // a task polled again before being woken registers only once
+            if !wakers.entries.iter().any(|(key, w)| key.is_none() && w.will_wake(waker)) {
+                wakers.entries.push((None, waker.clone()));
+                self.len.store(wakers.entries.len(), Ordering::Relaxed);
+            }
+        }
+        atomic::fence(Ordering::SeqCst);
+    }
+
+    /// Registers `waker` under `key`, which is assigned on first use and
+    /// replaces the entry of an earlier registration still pending.
+    pub fn register_keyed(&self, key: &mut Option<usize>, waker: &Waker) {
+        {
+            let mut wakers = self.wakers.lock().unwrap();
+            let key = *key.get_or_insert_with(|| {
+                wakers.keys += 1;
+                wakers.keys
+            });
+            match wakers.entries.iter_mut().find(|(k, _)| *k == Some(key)) {
+                Some((_, w)) => w.clone_from(waker),
+                None => wakers.entries.push((Some(key), waker.clone())),
+            }
+            self.len.store(wakers.entries.len(), Ordering::Relaxed);
+        }
+        atomic::fence(Ordering::SeqCst);
+    }
+
+    /// Drops the registrations of `waker`, for a task that stops waiting
+    /// without having been woken.
+    pub fn deregister(&self, waker: &Waker) {
+        self.retain(|key, w| key.is_some() || !w.will_wake(waker));
+    }
+
+    /// Drops the registration under `key`, for a future that goes away.
+    pub fn deregister_keyed(&self, key: usize) {
+        self.retain(|k, _| k != Some(key));
+    }
+
+    fn retain(&self, mut keep: impl FnMut(Option<usize>, &Waker) -> bool) {
+        let mut wakers = self.wakers.lock().unwrap();
+        wakers.entries.retain(|(key, w)| keep(*key, w));
+        self.len.store(wakers.entries.len(), Ordering::Relaxed);
+    }
+
+    /// Wakes the registered tasks after progress made by a `SeqCst`
+    /// read-modify-write, which orders it before the count is loaded.
+    #[inline]
+    pub fn wake(&self) {
+        if self.len.load(Ordering::SeqCst) > 0 {
+            let wakers = {
+                let mut wakers = self.wakers.lock().unwrap();
+                self.len.store(0, Ordering::Relaxed);
+                mem::take(&mut wakers.entries)
+            };
+            for (_, waker) in wakers {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Wakes the registered tasks after progress made by a weaker store.
+    #[inline]
+    pub fn wake_fenced(&self) {
+        atomic::fence(Ordering::SeqCst);
+        self.wake();
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.wakers.lock().unwrap().entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn test_park_stale_token() {
+        let park = Park::new();
+        let token = park.prepare();
+        park.notify();
+
+        // the epoch moved on, so this must return without parking
+        park.wait(token, 0, None);
+        assert_eq!(park.sleepers.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_park_notify() {
+        let park = Arc::new(Park::new());
+        let token = park.prepare();
+
+        let handle = {
+            let park = Arc::clone(&park);
+            thread::spawn(move || park.wait(token, 0, None))
+        };
+
+        while park.sleepers.load(Ordering::SeqCst) == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        park.notify();
+        handle.join().unwrap();
+    }
+
+    struct Counter(AtomicUsize);
+
+    impl std::task::Wake for Counter {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_wakers() {
+        let counter = Arc::new(Counter(AtomicUsize::new(0)));
+        let waker = Waker::from(Arc::clone(&counter));
+        let wakers = Wakers::default();
+
+        // nobody registered, nothing to take
+        wakers.wake();
+        wakers.wake_fenced();
+
+        wakers.register(&waker);
+        wakers.register(&waker);
+        assert_eq!(wakers.len(), 1);
+        wakers.wake();
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert_eq!(wakers.len(), 0);
+
+        wakers.register(&waker);
+        wakers.wake_fenced();
+        wakers.wake();
+        assert_eq!(counter.0.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_wakers_keyed() {
+        let counter = Arc::new(Counter(AtomicUsize::new(0)));
+        let waker = Waker::from(Arc::clone(&counter));
+        let wakers = Wakers::default();
+
+        // two futures of the same task, each with an entry of its own
+        let (mut first, mut second) = (None, None);
+        wakers.register_keyed(&mut first, &waker);
+        wakers.register_keyed(&mut first, &waker);
+        wakers.register_keyed(&mut second, &waker);
+        assert_eq!(wakers.len(), 2);
+
+        // dropping one leaves the other registered
+        wakers.deregister_keyed(first.unwrap());
+        wakers.deregister(&waker);
+        assert_eq!(wakers.len(), 1);
+        wakers.wake();
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_park_deadline() {
+        let park = Park::new();
+        let token = park.prepare();
+        let deadline = Instant::now() + Duration::from_millis(10);
+
+        // nobody notifies, the deadline alone must bring us back
+        park.wait(token, 0, Some(deadline));
+        assert_eq!(park.sleepers.load(Ordering::SeqCst), 0);
+        assert!(park.threads.lock().unwrap().is_empty());
+    }
+}